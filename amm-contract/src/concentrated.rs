@@ -0,0 +1,978 @@
+//! Uniswap v3-style concentrated liquidity.
+//!
+//! Liquidity is provided by positions in a range of ticks, a price of a tick `i` is `1.0001^i`
+//! tokens B per token A. The pool keeps a square root of the price in a fixed-point Q64.64
+//! and the liquidity that is active at the current tick. Swaps move the price tick by tick
+//! and fees are accrued to the active liquidity as a fee growth per unit of liquidity.
+//!
+//! All amounts are normalized to the same decimals by a caller.
+
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{TreeMap, UnorderedMap},
+    env,
+    json_types::U128,
+    AccountId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::token::FEE_DIVISOR;
+
+pub use u512::U512;
+
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod u512 {
+    uint::construct_uint! {
+        /// 512-bit unsigned integer, products of Q64.64 prices and liquidity don't fit into 256 bits
+        pub struct U512(8);
+    }
+}
+
+/// The lowest tick, the price of a token A is about `2^-64`
+pub const MIN_TICK: i32 = -MAX_TICK;
+/// The highest tick, the price of a token A is about `2^64`
+pub const MAX_TICK: i32 = 443_636;
+/// `1.0` in the Q64.64 fixed-point
+pub const Q64: u128 = 1 << 64;
+
+/// `2^128 / 1.0001^(2^i / 2)` in a Q128.128 fixed-point, they are used to calculate
+/// a square root of a tick price bit by bit
+const SQRT_PRICE_FACTORS: [u128; 19] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+];
+
+/// State of an initialized tick, it's a bound of at least one position
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Tick {
+    /// Total liquidity of positions that use the tick as a bound
+    pub liquidity_gross: u128,
+    /// Liquidity that is added when the price crosses the tick from left to right
+    pub liquidity_net: i128,
+    /// Fee growth on the other side of the tick from the current one
+    pub fee_growth_outside_a: u128,
+    pub fee_growth_outside_b: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Position {
+    pub owner_id: AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: u128,
+    /// Fee growth inside the range at the last update of the position
+    pub fee_growth_inside_a: u128,
+    pub fee_growth_inside_b: u128,
+    /// Fees that are accrued, but not collected yet
+    pub fees_a: u128,
+    pub fees_b: u128,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PositionView {
+    pub owner_id: AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: U128,
+    pub fees_a: U128,
+    pub fees_b: U128,
+}
+
+/// Result of a swap calculation, it's applied to the pool state only by a real swap
+pub struct SwapResult {
+    pub amount_in: u128,
+    pub amount_out: u128,
+    sqrt_price: u128,
+    tick: i32,
+    liquidity: u128,
+    fee_growth: u128,
+    /// Crossed ticks with a fee growth of the input token at the moment of crossing
+    crossed_ticks: Vec<(i32, u128)>,
+}
+
+impl SwapResult {
+    /// Price of a token A in tokens B in Q64.64 after the swap
+    pub fn price(&self) -> u128 {
+        mul_div(self.sqrt_price, self.sqrt_price, Q64, false)
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ConcentratedPool {
+    pub tick_spacing: u32,
+    /// Square root of the price in Q64.64
+    pub sqrt_price: u128,
+    /// The greatest tick with a price that is not greater than the current one
+    pub tick: i32,
+    /// Liquidity of positions with a range that contains the current tick
+    pub liquidity: u128,
+    /// Fees per unit of liquidity in Q64.64, accumulated from the pool creation.
+    /// They could overflow, only differences between them are meaningful.
+    pub fee_growth_a: u128,
+    pub fee_growth_b: u128,
+    ticks: TreeMap<i32, Tick>,
+    positions: UnorderedMap<u64, Position>,
+    next_position_id: u64,
+}
+
+impl ConcentratedPool {
+    pub fn new(tick_spacing: u32, initial_tick: i32) -> Self {
+        assert_valid_config(tick_spacing, initial_tick);
+        Self {
+            tick_spacing,
+            sqrt_price: sqrt_price_at_tick(initial_tick),
+            tick: initial_tick,
+            liquidity: 0,
+            fee_growth_a: 0,
+            fee_growth_b: 0,
+            ticks: TreeMap::new(b"t".to_vec()),
+            positions: UnorderedMap::new(b"p".to_vec()),
+            next_position_id: 0,
+        }
+    }
+
+    /// Price of a token A in tokens B in Q64.64
+    pub fn price(&self) -> u128 {
+        mul_div(self.sqrt_price, self.sqrt_price, Q64, false)
+    }
+
+    pub fn position(&self, position_id: u64) -> Option<PositionView> {
+        self.positions.get(&position_id).map(|position| {
+            // Fees that are earned since the last update of the position are included
+            let (fee_growth_inside_a, fee_growth_inside_b) =
+                self.fee_growth_inside(position.lower_tick, position.upper_tick);
+            let fees_a = position.fees_a
+                + earned_fees(
+                    position.liquidity,
+                    fee_growth_inside_a,
+                    position.fee_growth_inside_a,
+                );
+            let fees_b = position.fees_b
+                + earned_fees(
+                    position.liquidity,
+                    fee_growth_inside_b,
+                    position.fee_growth_inside_b,
+                );
+
+            PositionView {
+                owner_id: position.owner_id,
+                lower_tick: position.lower_tick,
+                upper_tick: position.upper_tick,
+                liquidity: position.liquidity.into(),
+                fees_a: fees_a.into(),
+                fees_b: fees_b.into(),
+            }
+        })
+    }
+
+    /// Creates a position with the greatest liquidity that `amount_a` and `amount_b` allow,
+    /// returns an id of the position and amounts that should be deposited
+    pub fn open_position(
+        &mut self,
+        owner_id: AccountId,
+        lower_tick: i32,
+        upper_tick: i32,
+        amount_a: u128,
+        amount_b: u128,
+    ) -> (u64, u128, u128) {
+        self.assert_valid_range(lower_tick, upper_tick);
+
+        let sqrt_price_lower = sqrt_price_at_tick(lower_tick);
+        let sqrt_price_upper = sqrt_price_at_tick(upper_tick);
+        let liquidity = liquidity_for_amounts(
+            self.sqrt_price,
+            sqrt_price_lower,
+            sqrt_price_upper,
+            amount_a,
+            amount_b,
+        );
+        if liquidity == 0 {
+            env::panic_str("Provided amounts are too small to open a position");
+        }
+
+        let position_id = self.next_position_id;
+        self.next_position_id += 1;
+        self.positions.insert(
+            &position_id,
+            &Position {
+                owner_id,
+                lower_tick,
+                upper_tick,
+                liquidity: 0,
+                fee_growth_inside_a: 0,
+                fee_growth_inside_b: 0,
+                fees_a: 0,
+                fees_b: 0,
+            },
+        );
+
+        self.modify_position(position_id, liquidity as i128);
+        let (amount_a, amount_b) = amounts_for_liquidity(
+            self.sqrt_price,
+            sqrt_price_lower,
+            sqrt_price_upper,
+            liquidity,
+            true,
+        );
+        (position_id, amount_a, amount_b)
+    }
+
+    /// Removes a position of an `owner_id`, returns its amounts together with accrued fees
+    pub fn close_position(&mut self, owner_id: &AccountId, position_id: u64) -> (u128, u128) {
+        let position = self.owned_position(owner_id, position_id);
+        let (amount_a, amount_b) = amounts_for_liquidity(
+            self.sqrt_price,
+            sqrt_price_at_tick(position.lower_tick),
+            sqrt_price_at_tick(position.upper_tick),
+            position.liquidity,
+            false,
+        );
+        self.modify_position(position_id, -(position.liquidity as i128));
+
+        let position = self.positions.remove(&position_id).unwrap();
+        (amount_a + position.fees_a, amount_b + position.fees_b)
+    }
+
+    /// Returns accrued fees of a position and resets them
+    pub fn collect_fees(&mut self, owner_id: &AccountId, position_id: u64) -> (u128, u128) {
+        self.owned_position(owner_id, position_id);
+        self.modify_position(position_id, 0);
+
+        let mut position = self.positions.get(&position_id).unwrap();
+        let fees = (position.fees_a, position.fees_b);
+        position.fees_a = 0;
+        position.fees_b = 0;
+        self.positions.insert(&position_id, &position);
+        fees
+    }
+
+    /// Calculates a swap without changing the pool state. An `amount` is an input
+    /// if `exact_in` or an output otherwise, the `fee` is taken from the input.
+    pub fn calc_swap(
+        &self,
+        a_to_b: bool,
+        amount: u128,
+        exact_in: bool,
+        fee: u32,
+        protocol_fee: u32,
+    ) -> SwapResult {
+        let mut result = SwapResult {
+            amount_in: 0,
+            amount_out: 0,
+            sqrt_price: self.sqrt_price,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            fee_growth: if a_to_b {
+                self.fee_growth_a
+            } else {
+                self.fee_growth_b
+            },
+            crossed_ticks: vec![],
+        };
+
+        let mut amount_remaining = amount;
+        while amount_remaining > 0 {
+            let next_tick = if a_to_b {
+                self.ticks.floor_key(&result.tick).unwrap_or(MIN_TICK)
+            } else {
+                self.ticks.higher(&result.tick).unwrap_or(MAX_TICK)
+            };
+            let sqrt_price_target = sqrt_price_at_tick(next_tick);
+            if sqrt_price_target == result.sqrt_price
+                && (next_tick == MIN_TICK || next_tick == MAX_TICK)
+            {
+                env::panic_str("Pool doesn't have enough liquidity for the swap");
+            }
+
+            let step = swap_step(
+                result.sqrt_price,
+                sqrt_price_target,
+                result.liquidity,
+                amount_remaining,
+                exact_in,
+                fee,
+            );
+            if exact_in {
+                amount_remaining -= step.amount_in + step.fee_amount;
+            } else {
+                amount_remaining -= step.amount_out;
+            }
+            result.amount_in += step.amount_in + step.fee_amount;
+            result.amount_out += step.amount_out;
+
+            if result.liquidity > 0 {
+                // The protocol part is rounded up, so positions never earn more than the pool keeps
+                let lp_fee_amount = step.fee_amount
+                    - mul_div(
+                        step.fee_amount,
+                        protocol_fee.into(),
+                        FEE_DIVISOR.into(),
+                        true,
+                    );
+                result.fee_growth = result.fee_growth.wrapping_add(mul_div(
+                    lp_fee_amount,
+                    Q64,
+                    result.liquidity,
+                    false,
+                ));
+            }
+
+            if step.sqrt_price == sqrt_price_target {
+                if let Some(tick) = self.ticks.get(&next_tick) {
+                    let liquidity_net = if a_to_b {
+                        -tick.liquidity_net
+                    } else {
+                        tick.liquidity_net
+                    };
+                    result.liquidity = add_liquidity_delta(result.liquidity, liquidity_net);
+                    result.crossed_ticks.push((next_tick, result.fee_growth));
+                }
+                result.tick = if a_to_b { next_tick - 1 } else { next_tick };
+            } else {
+                result.tick = tick_at_sqrt_price(step.sqrt_price);
+            }
+            result.sqrt_price = step.sqrt_price;
+        }
+
+        result
+    }
+
+    /// Applies a calculated swap to the pool state
+    pub fn apply_swap(&mut self, a_to_b: bool, result: SwapResult) {
+        let (fee_growth_a, fee_growth_b) = (self.fee_growth_a, self.fee_growth_b);
+        for (tick_index, fee_growth) in result.crossed_ticks {
+            // The fee growth of the other token isn't changed by the swap
+            let (fee_growth_a, fee_growth_b) = if a_to_b {
+                (fee_growth, fee_growth_b)
+            } else {
+                (fee_growth_a, fee_growth)
+            };
+            let mut tick = self.ticks.get(&tick_index).unwrap();
+            tick.fee_growth_outside_a = fee_growth_a.wrapping_sub(tick.fee_growth_outside_a);
+            tick.fee_growth_outside_b = fee_growth_b.wrapping_sub(tick.fee_growth_outside_b);
+            self.ticks.insert(&tick_index, &tick);
+        }
+
+        if a_to_b {
+            self.fee_growth_a = result.fee_growth;
+        } else {
+            self.fee_growth_b = result.fee_growth;
+        }
+        self.sqrt_price = result.sqrt_price;
+        self.tick = result.tick;
+        self.liquidity = result.liquidity;
+    }
+
+    fn owned_position(&self, owner_id: &AccountId, position_id: u64) -> Position {
+        let position = self.positions.get(&position_id).unwrap_or_else(|| {
+            env::panic_str(format!("Position {position_id} not found").as_str())
+        });
+        if position.owner_id != *owner_id {
+            env::panic_str("Access unauthorized");
+        }
+        position
+    }
+
+    /// Changes a liquidity of a position and bounds of its range,
+    /// fees that are earned by the position since the last update are accrued
+    fn modify_position(&mut self, position_id: u64, liquidity_delta: i128) {
+        let mut position = self.positions.get(&position_id).unwrap();
+        let (lower_tick, upper_tick) = (position.lower_tick, position.upper_tick);
+
+        if liquidity_delta != 0 {
+            self.update_tick(lower_tick, liquidity_delta, false);
+            self.update_tick(upper_tick, liquidity_delta, true);
+        }
+
+        let (fee_growth_inside_a, fee_growth_inside_b) =
+            self.fee_growth_inside(lower_tick, upper_tick);
+        position.fees_a += earned_fees(
+            position.liquidity,
+            fee_growth_inside_a,
+            position.fee_growth_inside_a,
+        );
+        position.fees_b += earned_fees(
+            position.liquidity,
+            fee_growth_inside_b,
+            position.fee_growth_inside_b,
+        );
+        position.fee_growth_inside_a = fee_growth_inside_a;
+        position.fee_growth_inside_b = fee_growth_inside_b;
+        position.liquidity = add_liquidity_delta(position.liquidity, liquidity_delta);
+        self.positions.insert(&position_id, &position);
+
+        if lower_tick <= self.tick && self.tick < upper_tick {
+            self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta);
+        }
+
+        // Ticks without positions aren't needed anymore
+        for tick_index in [lower_tick, upper_tick] {
+            if let Some(tick) = self.ticks.get(&tick_index) {
+                if tick.liquidity_gross == 0 {
+                    self.ticks.remove(&tick_index);
+                }
+            }
+        }
+    }
+
+    fn update_tick(&mut self, tick_index: i32, liquidity_delta: i128, upper: bool) {
+        let mut tick = self.ticks.get(&tick_index).unwrap_or_default();
+        if tick.liquidity_gross == 0 && tick_index <= self.tick {
+            // By convention all fees before the initialization were earned below the tick
+            tick.fee_growth_outside_a = self.fee_growth_a;
+            tick.fee_growth_outside_b = self.fee_growth_b;
+        }
+
+        tick.liquidity_gross = add_liquidity_delta(tick.liquidity_gross, liquidity_delta);
+        tick.liquidity_net = if upper {
+            tick.liquidity_net - liquidity_delta
+        } else {
+            tick.liquidity_net + liquidity_delta
+        };
+        self.ticks.insert(&tick_index, &tick);
+    }
+
+    fn fee_growth_inside(&self, lower_tick: i32, upper_tick: i32) -> (u128, u128) {
+        let lower = self.ticks.get(&lower_tick).unwrap_or_default();
+        let upper = self.ticks.get(&upper_tick).unwrap_or_default();
+
+        let (below_a, below_b) = if self.tick >= lower_tick {
+            (lower.fee_growth_outside_a, lower.fee_growth_outside_b)
+        } else {
+            (
+                self.fee_growth_a.wrapping_sub(lower.fee_growth_outside_a),
+                self.fee_growth_b.wrapping_sub(lower.fee_growth_outside_b),
+            )
+        };
+        let (above_a, above_b) = if self.tick < upper_tick {
+            (upper.fee_growth_outside_a, upper.fee_growth_outside_b)
+        } else {
+            (
+                self.fee_growth_a.wrapping_sub(upper.fee_growth_outside_a),
+                self.fee_growth_b.wrapping_sub(upper.fee_growth_outside_b),
+            )
+        };
+
+        (
+            self.fee_growth_a
+                .wrapping_sub(below_a)
+                .wrapping_sub(above_a),
+            self.fee_growth_b
+                .wrapping_sub(below_b)
+                .wrapping_sub(above_b),
+        )
+    }
+
+    fn assert_valid_range(&self, lower_tick: i32, upper_tick: i32) {
+        let tick_spacing = self.tick_spacing as i32;
+        if lower_tick >= upper_tick
+            || lower_tick < MIN_TICK
+            || upper_tick > MAX_TICK
+            || lower_tick % tick_spacing != 0
+            || upper_tick % tick_spacing != 0
+        {
+            env::panic_str(
+                format!(
+                    "Incorrect range of ticks {lower_tick}..{upper_tick}, \
+                    they should be multiples of {tick_spacing} in {MIN_TICK}..={MAX_TICK}"
+                )
+                .as_str(),
+            );
+        }
+    }
+}
+
+pub fn assert_valid_config(tick_spacing: u32, initial_tick: i32) {
+    if tick_spacing == 0 || tick_spacing > MAX_TICK as u32 {
+        env::panic_str(format!("Incorrect tick spacing {tick_spacing}").as_str());
+    }
+    if !(MIN_TICK..=MAX_TICK).contains(&initial_tick) {
+        env::panic_str(format!("Initial tick {initial_tick} is out of range").as_str());
+    }
+}
+
+/// Calculates a square root of `1.0001^tick` in Q64.64
+pub fn sqrt_price_at_tick(tick: i32) -> u128 {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        env::panic_str(format!("Tick {tick} is out of range").as_str());
+    }
+
+    // Ratio for a negative tick is calculated first, it's less than 1 in Q128.128
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = U512::one() << 128;
+    for (bit, factor) in SQRT_PRICE_FACTORS.iter().enumerate() {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = (ratio * U512::from(*factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = (U512::one() << 256) / ratio;
+    }
+
+    // Q128.128 to Q64.64, rounding up
+    let ratio = (ratio + (U512::one() << 64) - 1) >> 64;
+    ratio.as_u128()
+}
+
+/// Finds the greatest tick with a square root of a price not greater than a `sqrt_price`
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> i32 {
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(middle) <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+/// Amount of a token A between square roots of prices, `L * (sb - sa) / (sa * sb)`
+pub fn amount_a_delta(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> u128 {
+    let (sqrt_price_a, sqrt_price_b) = sorted(sqrt_price_a, sqrt_price_b);
+    let numerator = U512::from(liquidity) << 64;
+    let numerator = numerator * U512::from(sqrt_price_b - sqrt_price_a);
+    let denominator = U512::from(sqrt_price_a) * U512::from(sqrt_price_b);
+    div(numerator, denominator, round_up)
+}
+
+/// Amount of a token B between square roots of prices, `L * (sb - sa)`
+pub fn amount_b_delta(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> u128 {
+    let (sqrt_price_a, sqrt_price_b) = sorted(sqrt_price_a, sqrt_price_b);
+    mul_div(liquidity, sqrt_price_b - sqrt_price_a, Q64, round_up)
+}
+
+/// Calculates the greatest liquidity of a range, that `amount_a` and `amount_b` cover
+pub fn liquidity_for_amounts(
+    sqrt_price: u128,
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    amount_a: u128,
+    amount_b: u128,
+) -> u128 {
+    // L = x * sa * sb / (sb - sa)
+    let liquidity_a = |sqrt_price_a: u128| {
+        let numerator =
+            U512::from(amount_a) * U512::from(sqrt_price_a) * U512::from(sqrt_price_upper);
+        let denominator = U512::from(sqrt_price_upper - sqrt_price_a) << 64;
+        to_u128(numerator / denominator)
+    };
+    // L = y / (sb - sa)
+    let liquidity_b =
+        |sqrt_price_b: u128| mul_div(amount_b, Q64, sqrt_price_b - sqrt_price_lower, false);
+
+    if sqrt_price <= sqrt_price_lower {
+        liquidity_a(sqrt_price_lower)
+    } else if sqrt_price < sqrt_price_upper {
+        liquidity_a(sqrt_price).min(liquidity_b(sqrt_price))
+    } else {
+        liquidity_b(sqrt_price_upper)
+    }
+}
+
+/// Calculates amounts of tokens A and B of a `liquidity` in a range
+pub fn amounts_for_liquidity(
+    sqrt_price: u128,
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> (u128, u128) {
+    if sqrt_price <= sqrt_price_lower {
+        (
+            amount_a_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up),
+            0,
+        )
+    } else if sqrt_price < sqrt_price_upper {
+        (
+            amount_a_delta(sqrt_price, sqrt_price_upper, liquidity, round_up),
+            amount_b_delta(sqrt_price_lower, sqrt_price, liquidity, round_up),
+        )
+    } else {
+        (
+            0,
+            amount_b_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up),
+        )
+    }
+}
+
+struct SwapStep {
+    sqrt_price: u128,
+    amount_in: u128,
+    amount_out: u128,
+    fee_amount: u128,
+}
+
+/// Swaps inside a single tick range, from a `sqrt_price` to at most a `sqrt_price_target`.
+/// Input amounts are rounded up and output amounts are rounded down.
+fn swap_step(
+    sqrt_price: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u128,
+    exact_in: bool,
+    fee: u32,
+) -> SwapStep {
+    let a_to_b = sqrt_price >= sqrt_price_target;
+    let amount_in_delta = |from: u128, to: u128, round_up: bool| {
+        if a_to_b {
+            amount_a_delta(from, to, liquidity, round_up)
+        } else {
+            amount_b_delta(from, to, liquidity, round_up)
+        }
+    };
+    let amount_out_delta = |from: u128, to: u128| {
+        if a_to_b {
+            amount_b_delta(from, to, liquidity, false)
+        } else {
+            amount_a_delta(from, to, liquidity, false)
+        }
+    };
+
+    let next_sqrt_price = if exact_in {
+        let amount_less_fee =
+            amount_remaining * u128::from(FEE_DIVISOR - fee) / u128::from(FEE_DIVISOR);
+        if amount_less_fee >= amount_in_delta(sqrt_price, sqrt_price_target, true) {
+            sqrt_price_target
+        } else {
+            next_sqrt_price_from_input(sqrt_price, liquidity, amount_less_fee, a_to_b)
+        }
+    } else if amount_remaining >= amount_out_delta(sqrt_price, sqrt_price_target) {
+        sqrt_price_target
+    } else {
+        next_sqrt_price_from_output(sqrt_price, liquidity, amount_remaining, a_to_b)
+    };
+
+    let amount_in = amount_in_delta(sqrt_price, next_sqrt_price, true);
+    let mut amount_out = amount_out_delta(sqrt_price, next_sqrt_price);
+    if !exact_in {
+        amount_out = amount_out.min(amount_remaining);
+    }
+
+    let fee_amount = if exact_in && next_sqrt_price != sqrt_price_target {
+        // The rest of the input is taken as a fee
+        amount_remaining - amount_in
+    } else {
+        mul_div(amount_in, fee.into(), (FEE_DIVISOR - fee).into(), true)
+    };
+
+    SwapStep {
+        sqrt_price: next_sqrt_price,
+        amount_in,
+        amount_out,
+        fee_amount,
+    }
+}
+
+/// The price moves down for an input of a token A and up for an input of a token B,
+/// it's rounded so the pool receives not less than the input
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u128,
+    a_to_b: bool,
+) -> u128 {
+    if a_to_b {
+        // L * sp / (L + x * sp), rounding up
+        let numerator = U512::from(liquidity) << 64;
+        let denominator = numerator + U512::from(amount) * U512::from(sqrt_price);
+        div(numerator * U512::from(sqrt_price), denominator, true)
+    } else {
+        // sp + y / L, rounding down
+        sqrt_price + mul_div(amount, Q64, liquidity, false)
+    }
+}
+
+/// The price moves up for an output of a token A and down for an output of a token B,
+/// it's rounded so the pool sends not more than the output
+fn next_sqrt_price_from_output(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u128,
+    a_to_b: bool,
+) -> u128 {
+    if a_to_b {
+        // sp - y / L, rounding down
+        let delta = mul_div(amount, Q64, liquidity, true);
+        if delta >= sqrt_price {
+            env::panic_str("Pool doesn't have enough liquidity for the swap");
+        }
+        sqrt_price - delta
+    } else {
+        // L * sp / (L - x * sp), rounding up
+        let numerator = U512::from(liquidity) << 64;
+        let product = U512::from(amount) * U512::from(sqrt_price);
+        if product >= numerator {
+            env::panic_str("Pool doesn't have enough liquidity for the swap");
+        }
+        div(
+            numerator * U512::from(sqrt_price),
+            numerator - product,
+            true,
+        )
+    }
+}
+
+/// Fees of a `liquidity` for a growth of fees inside its range, rounding down
+fn earned_fees(liquidity: u128, fee_growth_inside: u128, last_fee_growth_inside: u128) -> u128 {
+    mul_div(
+        liquidity,
+        fee_growth_inside.wrapping_sub(last_fee_growth_inside),
+        Q64,
+        false,
+    )
+}
+
+fn add_liquidity_delta(liquidity: u128, liquidity_delta: i128) -> u128 {
+    if liquidity_delta < 0 {
+        liquidity - liquidity_delta.unsigned_abs()
+    } else {
+        liquidity + liquidity_delta as u128
+    }
+}
+
+fn sorted(a: u128, b: u128) -> (u128, u128) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Calculates `a * b / c` in 512 bits
+fn mul_div(a: u128, b: u128, c: u128, round_up: bool) -> u128 {
+    div(U512::from(a) * U512::from(b), U512::from(c), round_up)
+}
+
+fn div(numerator: U512, denominator: U512, round_up: bool) -> u128 {
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    if round_up && !remainder.is_zero() {
+        to_u128(quotient + 1)
+    } else {
+        to_u128(quotient)
+    }
+}
+
+fn to_u128(value: U512) -> u128 {
+    if value > U512::from(u128::MAX) {
+        env::panic_str("Concentrated liquidity amount overflows");
+    }
+    value.as_u128()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEE: u32 = 30;
+
+    fn alice() -> AccountId {
+        "alice.near".parse().unwrap()
+    }
+
+    fn bob() -> AccountId {
+        "bob.near".parse().unwrap()
+    }
+
+    /// Swaps an exact input and returns the output
+    fn swap(pool: &mut ConcentratedPool, a_to_b: bool, amount: u128, protocol_fee: u32) -> u128 {
+        let result = pool.calc_swap(a_to_b, amount, true, FEE, protocol_fee);
+        assert_eq!(amount, result.amount_in);
+        let amount_out = result.amount_out;
+        pool.apply_swap(a_to_b, result);
+        amount_out
+    }
+
+    #[test]
+    fn test_sqrt_price_at_tick() {
+        assert_eq!(Q64, sqrt_price_at_tick(0));
+        // sqrt(1.0001) = 1.00004999875... in Q64.64, rounded up
+        assert_eq!(18_447_666_387_855_959_851, sqrt_price_at_tick(1));
+        assert_eq!(18_445_821_805_675_392_312, sqrt_price_at_tick(-1));
+        assert!(sqrt_price_at_tick(MAX_TICK) < u128::MAX >> 32);
+        assert!(sqrt_price_at_tick(MIN_TICK) > 0);
+
+        let mut previous = 0;
+        for tick in (MIN_TICK..=MAX_TICK).step_by(9_973) {
+            let sqrt_price = sqrt_price_at_tick(tick);
+            assert!(sqrt_price > previous);
+            assert_eq!(tick, tick_at_sqrt_price(sqrt_price));
+            assert_eq!(tick, tick_at_sqrt_price(sqrt_price_at_tick(tick + 1) - 1));
+            previous = sqrt_price;
+        }
+    }
+
+    #[test]
+    fn test_liquidity_for_amounts() {
+        let (lower, upper) = (sqrt_price_at_tick(-600), sqrt_price_at_tick(600));
+        for sqrt_price in [sqrt_price_at_tick(-1_000), Q64, sqrt_price_at_tick(1_000)] {
+            let liquidity =
+                liquidity_for_amounts(sqrt_price, lower, upper, 1_000_000_000, 2_000_000_000);
+            assert!(liquidity > 0);
+
+            // Required amounts don't exceed provided ones
+            let (amount_a, amount_b) =
+                amounts_for_liquidity(sqrt_price, lower, upper, liquidity, true);
+            assert!(amount_a <= 1_000_000_000 && amount_b <= 2_000_000_000);
+            assert!(
+                amount_a == 1_000_000_000 || amount_b == 2_000_000_000 || amount_a * amount_b == 0
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_in_range_is_better_than_full_range() {
+        let mut pool = ConcentratedPool::new(60, 0);
+        let (_, amount_a, amount_b) =
+            pool.open_position(alice(), -600, 600, 1_000_000_000, 1_000_000_000);
+        assert_eq!((1_000_000_000, 1_000_000_000), (amount_a, amount_b));
+
+        // The same amounts in the range give a deeper liquidity than `X * Y = K`
+        let amount_out = swap(&mut pool, true, 10_000_000, 0);
+        let constant_product_amount = crate::token::calc_transfer_amount(
+            1_000_000_000.into(),
+            1_000_000_000.into(),
+            10_000_000.into(),
+            FEE,
+        );
+        assert!(amount_out > constant_product_amount.0);
+        assert!(amount_out < 10_000_000);
+        assert!(pool.tick < 0);
+    }
+
+    #[test]
+    fn test_swap_crosses_ticks() {
+        let mut pool = ConcentratedPool::new(10, 0);
+        pool.open_position(alice(), -100, 100, 1_000_000, 1_000_000);
+        pool.open_position(bob(), -1_000, -100, 0, 5_000_000);
+        let in_range_liquidity = pool.liquidity;
+
+        // The first position runs out of a token B and the second one is activated
+        swap(&mut pool, true, 3_000_000, 0);
+        assert!(pool.tick < -100);
+        assert_ne!(in_range_liquidity, pool.liquidity);
+
+        // The price returns to the first range
+        swap(&mut pool, false, 3_500_000, 0);
+        assert!(pool.tick >= -100);
+        assert_eq!(in_range_liquidity, pool.liquidity);
+    }
+
+    #[test]
+    fn test_fees_accrue_only_in_range() {
+        let mut pool = ConcentratedPool::new(10, 0);
+        let (in_range, _, _) = pool.open_position(alice(), -100, 100, 1_000_000, 1_000_000);
+        let (out_of_range, _, _) = pool.open_position(bob(), 200, 300, 1_000_000, 0);
+
+        swap(&mut pool, true, 100_000, 0);
+        swap(&mut pool, false, 100_000, 0);
+
+        let position = pool.position(in_range).unwrap();
+        let (fees_a, fees_b) = pool.collect_fees(&alice(), in_range);
+        assert_eq!((position.fees_a.0, position.fees_b.0), (fees_a, fees_b));
+        // 0.3% of each input, rounded down
+        assert!((299..=300).contains(&fees_a) && (299..=300).contains(&fees_b));
+        assert_eq!((0, 0), pool.collect_fees(&alice(), in_range));
+        assert_eq!((0, 0), pool.collect_fees(&bob(), out_of_range));
+    }
+
+    #[test]
+    fn test_positions_are_covered_by_pool() {
+        let mut pool = ConcentratedPool::new(10, 0);
+        let mut balance_a = 0;
+        let mut balance_b = 0;
+        let mut positions = vec![];
+        for (owner_id, lower_tick, upper_tick) in [
+            (alice(), -500, 500),
+            (bob(), -2_000, -100),
+            (bob(), 100, 3_000),
+        ] {
+            let (position_id, amount_a, amount_b) = pool.open_position(
+                owner_id.clone(),
+                lower_tick,
+                upper_tick,
+                5_000_000,
+                5_000_000,
+            );
+            balance_a += amount_a;
+            balance_b += amount_b;
+            positions.push((owner_id, position_id));
+        }
+
+        let protocol_fee = 2_000;
+        for (a_to_b, amount) in [(true, 3_000_000), (false, 7_000_000), (true, 1_234_567)] {
+            let result = pool.calc_swap(a_to_b, amount, true, FEE, protocol_fee);
+            let protocol_fee_amount =
+                amount * u128::from(FEE) / 10_000 * u128::from(protocol_fee) / 10_000;
+            let amount_in = amount - protocol_fee_amount;
+            if a_to_b {
+                balance_a += amount_in;
+                balance_b -= result.amount_out;
+            } else {
+                balance_b += amount_in;
+                balance_a -= result.amount_out;
+            }
+            pool.apply_swap(a_to_b, result);
+        }
+
+        for (owner_id, position_id) in positions {
+            let (amount_a, amount_b) = pool.close_position(&owner_id, position_id);
+            balance_a -= amount_a;
+            balance_b -= amount_b;
+        }
+        assert_eq!(0, pool.liquidity);
+        // Only rounding dust is left in the pool
+        assert!(balance_a < 10 && balance_b < 10);
+    }
+
+    #[test]
+    fn test_exact_out_swap() {
+        let mut pool = ConcentratedPool::new(10, 0);
+        pool.open_position(alice(), -1_000, 1_000, 10_000_000, 10_000_000);
+
+        let result = pool.calc_swap(false, 1_000_000, false, FEE, 0);
+        assert_eq!(1_000_000, result.amount_out);
+
+        // The same input swaps to not less than the exact output
+        let exact_in = pool.calc_swap(false, result.amount_in, true, FEE, 0);
+        assert!(exact_in.amount_out >= 1_000_000);
+        let smaller_in = pool.calc_swap(false, result.amount_in - 1, true, FEE, 0);
+        assert!(smaller_in.amount_out <= 1_000_000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_range() {
+        let mut pool = ConcentratedPool::new(10, 0);
+        pool.open_position(alice(), -15, 100, 1_000, 1_000);
+    }
+}
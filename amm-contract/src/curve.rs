@@ -0,0 +1,371 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
+    json_types::U128,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::concentrated;
+use crate::token::{self, FEE_DIVISOR, U256};
+use crate::weighted;
+
+/// Number of tokens in a pool
+const N_COINS: u128 = 2;
+/// Maximum number of Newton iterations, usually it converges in a few steps
+const MAX_ITERATIONS: usize = 256;
+/// Upper bound of the amplification coefficient
+pub const MAX_AMP: u64 = 1_000_000;
+
+/// Pricing function of a pool, it's selected at the pool initialization
+///
+/// # Examples
+/// ```json
+/// {"type":"stable_swap","amp":100}
+/// {"type":"weighted","weight_a":8000,"weight_b":2000}
+/// {"type":"concentrated","tick_spacing":10,"initial_tick":0}
+/// ```
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Debug, Default, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde", tag = "type", rename_all = "snake_case")]
+pub enum Curve {
+    /// `X * Y = K`
+    #[default]
+    ConstantProduct,
+    /// Curve-style StableSwap invariant, a higher `amp` keeps a price closer to 1:1
+    StableSwap { amp: u64 },
+    /// Balancer-style `X^Wa * Y^Wb = K`, weights are in basis points and their sum is 100%
+    Weighted { weight_a: u32, weight_b: u32 },
+    /// Uniswap v3-style liquidity of positions in price ranges, the price starts
+    /// at `1.0001^initial_tick` and bounds of ranges are multiples of `tick_spacing`
+    Concentrated {
+        tick_spacing: u32,
+        initial_tick: i32,
+    },
+}
+
+impl Curve {
+    /// Calculates a swap output, all amounts should be in the same decimals
+    pub fn amount_out(
+        &self,
+        src_pool_balance: U128,
+        dst_pool_balance: U128,
+        amount: U128,
+        fee: u32,
+    ) -> U128 {
+        match self {
+            Curve::ConstantProduct => {
+                token::calc_transfer_amount(src_pool_balance, dst_pool_balance, amount, fee)
+            }
+            Curve::StableSwap { amp } => {
+                stable_amount_out(*amp, src_pool_balance, dst_pool_balance, amount, fee)
+            }
+            Curve::Weighted { weight_a, weight_b } => weighted::weighted_amount_out(
+                *weight_a,
+                *weight_b,
+                src_pool_balance,
+                dst_pool_balance,
+                amount,
+                fee,
+            ),
+            Curve::Concentrated { .. } => concentrated_panic(),
+        }
+    }
+
+    /// Calculates a swap input for an exact output, all amounts should be in the same decimals
+    pub fn amount_in(
+        &self,
+        src_pool_balance: U128,
+        dst_pool_balance: U128,
+        amount_out: U128,
+        fee: u32,
+    ) -> U128 {
+        match self {
+            Curve::ConstantProduct => {
+                token::calc_amount_in(src_pool_balance, dst_pool_balance, amount_out, fee)
+            }
+            Curve::StableSwap { amp } => {
+                stable_amount_in(*amp, src_pool_balance, dst_pool_balance, amount_out, fee)
+            }
+            Curve::Weighted { weight_a, weight_b } => weighted::weighted_amount_in(
+                *weight_a,
+                *weight_b,
+                src_pool_balance,
+                dst_pool_balance,
+                amount_out,
+                fee,
+            ),
+            Curve::Concentrated { .. } => concentrated_panic(),
+        }
+    }
+
+    /// Swaps are calculated from a token A to a token B,
+    /// the reversed curve is used for swaps in the opposite direction
+    pub fn reversed(&self) -> Curve {
+        match self {
+            Curve::Weighted { weight_a, weight_b } => Curve::Weighted {
+                weight_a: *weight_b,
+                weight_b: *weight_a,
+            },
+            curve => curve.clone(),
+        }
+    }
+
+    /// Weights of tokens A and B, they are equal for unweighted curves
+    pub fn weights(&self) -> (u32, u32) {
+        match self {
+            Curve::Weighted { weight_a, weight_b } => (*weight_a, *weight_b),
+            _ => (1, 1),
+        }
+    }
+
+    /// Calculates LP shares for a provided liquidity, see [token::calc_shares].
+    /// The first deposit of a weighted pool receives [weighted::INIT_POOL_SUPPLY]
+    /// because a geometric mean of amounts doesn't reflect their weights.
+    pub fn shares(
+        &self,
+        liq_balance_a: U128,
+        liq_balance_b: U128,
+        amount_a: U128,
+        amount_b: U128,
+        total_shares: U128,
+    ) -> U128 {
+        match self {
+            Curve::Weighted { .. } if total_shares.0 == 0 => weighted::INIT_POOL_SUPPLY.into(),
+            Curve::Concentrated { .. } => concentrated_panic(),
+            _ => token::calc_shares(
+                liq_balance_a,
+                liq_balance_b,
+                amount_a,
+                amount_b,
+                total_shares,
+            ),
+        }
+    }
+
+    pub fn assert_valid(&self) {
+        match self {
+            Curve::ConstantProduct => {}
+            Curve::StableSwap { amp } => assert_valid_amp(*amp),
+            Curve::Weighted { weight_a, weight_b } => {
+                weighted::assert_valid_weights(*weight_a, *weight_b)
+            }
+            Curve::Concentrated {
+                tick_spacing,
+                initial_tick,
+            } => concentrated::assert_valid_config(*tick_spacing, *initial_tick),
+        }
+    }
+}
+
+/// Concentrated liquidity isn't described by pool balances, it's calculated by positions
+fn concentrated_panic() -> ! {
+    env::panic_str("Concentrated liquidity is calculated by positions, not by pool balances")
+}
+
+pub fn assert_valid_amp(amp: u64) {
+    if amp == 0 || amp > MAX_AMP {
+        env::panic_str(format!("Amplification {amp} should be in range 1..={MAX_AMP}").as_str());
+    }
+}
+
+/// StableSwap analogue of [token::calc_transfer_amount],
+/// the `fee` in basis points is deducted from the input `amount`
+pub fn stable_amount_out(
+    amp: u64,
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount: U128,
+    fee: u32,
+) -> U128 {
+    let amount_with_fee = token::mul_div(amount.0, (FEE_DIVISOR - fee).into(), FEE_DIVISOR.into());
+    let d = calc_d(amp, src_pool_balance.0, dst_pool_balance.0);
+    let y = calc_y(amp, src_pool_balance.0 + amount_with_fee, d);
+
+    // One unit is kept in the pool to cover a rounding of the iterations
+    dst_pool_balance
+        .0
+        .saturating_sub(y)
+        .saturating_sub(1)
+        .into()
+}
+
+/// StableSwap analogue of [token::calc_amount_in], the rounding is in favour of the pool
+pub fn stable_amount_in(
+    amp: u64,
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount_out: U128,
+    fee: u32,
+) -> U128 {
+    if amount_out >= dst_pool_balance {
+        env::panic_str(
+            format!(
+                "Pool doesn't have enough liquidity for {amount_out:?}, \
+                Pool balance: {dst_pool_balance:?}"
+            )
+            .as_str(),
+        );
+    }
+
+    let d = calc_d(amp, src_pool_balance.0, dst_pool_balance.0);
+    let x = calc_y(amp, dst_pool_balance.0 - amount_out.0, d);
+    let amount_with_fee = x.saturating_sub(src_pool_balance.0) + 1;
+
+    token::mul_div_ceil(
+        amount_with_fee,
+        FEE_DIVISOR.into(),
+        (FEE_DIVISOR - fee).into(),
+    )
+    .into()
+}
+
+/// Calculates the invariant `D` of balances `x` and `y` with the Newton iteration:
+///
+/// `A * n^n * (x + y) + D = A * n^n * D + D^(n + 1) / (n^n * x * y)`
+pub fn calc_d(amp: u64, x: u128, y: u128) -> u128 {
+    let sum = U256::from(x) + U256::from(y);
+    if sum.is_zero() {
+        return 0;
+    }
+
+    let n = U256::from(N_COINS);
+    let ann = U256::from(amp) * n * n;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = d;
+        for balance in [x, y] {
+            d_p = d_p * d / (U256::from(balance) * n);
+        }
+
+        let d_prev = d;
+        d = (ann * sum + d_p * n) * d / ((ann - 1) * d + (n + 1) * d_p);
+        if abs_diff(d, d_prev) <= U256::one() {
+            return d.as_u128();
+        }
+    }
+
+    env::panic_str("Invariant D doesn't converge")
+}
+
+/// Calculates a balance of one token, that keeps the invariant `d` for a balance `x`
+/// of another token, with the Newton iteration
+pub fn calc_y(amp: u64, x: u128, d: u128) -> u128 {
+    let n = U256::from(N_COINS);
+    let ann = U256::from(amp) * n * n;
+    let d = U256::from(d);
+    let x = U256::from(x);
+
+    let c = d * d / (x * n) * d / (ann * n);
+    let b = x + d / ann;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = (y * y + c) / (y * 2 + b - d);
+        if abs_diff(y, y_prev) <= U256::one() {
+            return y.as_u128();
+        }
+    }
+
+    env::panic_str("Balance Y doesn't converge")
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invariant_holds(amp: u64, src_pool_balance: u128, dst_pool_balance: u128) {
+        let d_before = calc_d(amp, src_pool_balance, dst_pool_balance);
+        for amount in [1, 10, 1_000, src_pool_balance / 100, src_pool_balance] {
+            for fee in [0, 4, 30] {
+                let amount_out = stable_amount_out(
+                    amp,
+                    src_pool_balance.into(),
+                    dst_pool_balance.into(),
+                    amount.into(),
+                    fee,
+                );
+                assert!(amount_out.0 < dst_pool_balance);
+
+                // The invariant never decreases after a swap
+                let d_after = calc_d(
+                    amp,
+                    src_pool_balance + amount,
+                    dst_pool_balance - amount_out.0,
+                );
+                assert!(d_after >= d_before);
+            }
+        }
+    }
+
+    #[test]
+    fn test_calc_d_of_balanced_pool() {
+        assert_eq!(2_000_000, calc_d(100, 1_000_000, 1_000_000));
+        assert_eq!(0, calc_d(100, 0, 0));
+    }
+
+    #[test]
+    fn test_calc_y_keeps_invariant() {
+        let d = calc_d(100, 1_000_000, 1_000_000);
+        let y = calc_y(100, 1_000_000, d);
+        assert!(abs_diff(U256::from(y), U256::from(1_000_000u128)) <= U256::one());
+    }
+
+    #[test]
+    fn test_stable_amount_out_near_peg() {
+        // A balanced stable pool swaps almost 1:1, unlike the constant product
+        let amount = stable_amount_out(100, 1_000_000.into(), 1_000_000.into(), 10_000.into(), 0);
+        let constant_product_amount =
+            token::calc_transfer_amount(1_000_000.into(), 1_000_000.into(), 10_000.into(), 0);
+        assert!(amount.0 > 9_990 && amount.0 < 10_000);
+        assert!(amount > constant_product_amount);
+    }
+
+    #[test]
+    fn test_stable_swap_invariant_holds() {
+        for amp in [1, 10, 100, 5_000] {
+            assert_invariant_holds(amp, 1_000_000, 1_000_000);
+            assert_invariant_holds(amp, 1_000_000, 3_000_000);
+            assert_invariant_holds(amp, 10u128.pow(24), 10u128.pow(23));
+        }
+    }
+
+    #[test]
+    fn test_stable_amount_in_favours_pool() {
+        let amp = 100;
+        let (src_pool_balance, dst_pool_balance) = (1_000_000u128, 2_000_000u128);
+        let d_before = calc_d(amp, src_pool_balance, dst_pool_balance);
+        for amount_out in [1, 100, 10_000, 1_000_000] {
+            let amount_in = stable_amount_in(
+                amp,
+                src_pool_balance.into(),
+                dst_pool_balance.into(),
+                amount_out.into(),
+                30,
+            );
+            let amount_with_fee =
+                token::mul_div(amount_in.0, (FEE_DIVISOR - 30).into(), FEE_DIVISOR.into());
+
+            let d_after = calc_d(
+                amp,
+                src_pool_balance + amount_with_fee,
+                dst_pool_balance - amount_out,
+            );
+            assert!(d_after >= d_before);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_stable_amount_in_more_than_pool() {
+        stable_amount_in(100, 1_000.into(), 1_000.into(), 1_000.into(), 0);
+    }
+}
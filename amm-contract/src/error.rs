@@ -0,0 +1,32 @@
+use near_sdk::{env, json_types::U128, serde_json};
+use serde::Serialize;
+
+use crate::pause::Operation;
+
+/// Errors that are reported as a JSON, so clients are able to parse them
+///
+/// # Examples
+/// ```json
+/// {"error":"slippage_exceeded","amount_out":"4","min_amount_out":"5"}
+/// ```
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "error", rename_all = "snake_case")]
+pub enum AmmError {
+    SlippageExceeded {
+        amount_out: U128,
+        min_amount_out: U128,
+    },
+    ExcessiveAmountIn {
+        amount_in: U128,
+        max_amount_in: U128,
+    },
+    /// The operation is paused by the owner
+    Paused { operation: Operation },
+}
+
+impl AmmError {
+    pub fn panic(&self) -> ! {
+        let message = serde_json::to_string(self).unwrap_or_else(|_| format!("{self:?}"));
+        env::panic_str(message.as_str())
+    }
+}
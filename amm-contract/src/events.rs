@@ -0,0 +1,204 @@
+//! NEP-297 events of the pool, they are logged as
+//! `EVENT_JSON:{"standard":"amm","version":"1.0.0","event":"swap","data":{...}}`.
+//!
+//! LP shares are a NEP-141 token, so their changes are logged by standard `ft_mint`,
+//! `ft_burn` and `ft_transfer` events as well.
+
+use near_sdk::{env, json_types::U128, serde_json, AccountId};
+use serde::Serialize;
+
+use crate::pause::Operation;
+use crate::roles::Role;
+
+const STANDARD: &str = "amm";
+const VERSION: &str = "1.0.0";
+
+#[must_use = "don't forget to `.emit()` this event"]
+#[derive(Serialize, Debug)]
+#[serde(
+    crate = "near_sdk::serde",
+    tag = "event",
+    content = "data",
+    rename_all = "snake_case"
+)]
+pub enum AmmEvent<'a> {
+    /// Transferred tokens are credited to an internal balance
+    Deposit {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Transferred tokens are returned to the sender by `ft_on_transfer`
+    Refund {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    Swap {
+        account_id: &'a AccountId,
+        token_in: &'a AccountId,
+        amount_in: U128,
+        token_out: &'a AccountId,
+        amount_out: U128,
+        /// Part of the `amount_in` that is accrued for the protocol
+        protocol_fee: U128,
+    },
+    /// A hop of a route is rolled back, because the next pool refunded its output
+    SwapRollback {
+        token_in: &'a AccountId,
+        amount_in: U128,
+        token_out: &'a AccountId,
+        amount_out: U128,
+    },
+    AddLiquidity {
+        account_id: &'a AccountId,
+        amount_a: U128,
+        amount_b: U128,
+        shares: U128,
+    },
+    RemoveLiquidity {
+        account_id: &'a AccountId,
+        amount_a: U128,
+        amount_b: U128,
+        shares: U128,
+    },
+    OpenPosition {
+        account_id: &'a AccountId,
+        position_id: u64,
+        lower_tick: i32,
+        upper_tick: i32,
+        amount_a: U128,
+        amount_b: U128,
+    },
+    ClosePosition {
+        account_id: &'a AccountId,
+        position_id: u64,
+        amount_a: U128,
+        amount_b: U128,
+    },
+    CollectFees {
+        account_id: &'a AccountId,
+        position_id: u64,
+        amount_a: U128,
+        amount_b: U128,
+    },
+    /// Tokens are debited from the internal balance and sent with `ft_transfer`
+    WithdrawStarted {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    WithdrawCompleted {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// The transfer failed and tokens are credited back to the internal balance
+    WithdrawFailed {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    ClaimProtocolFees {
+        treasury_id: &'a AccountId,
+        amount_a: U128,
+        amount_b: U128,
+    },
+    SetFee {
+        fee: u32,
+    },
+    SetProtocolFee {
+        protocol_fee: u32,
+    },
+    SetTreasury {
+        treasury_id: &'a AccountId,
+    },
+    SetAmp {
+        amp: u64,
+    },
+    SetLiquidityTolerance {
+        tolerance: u32,
+    },
+    SetPaused {
+        operation: Operation,
+        paused: bool,
+    },
+    SetWithdrawOnly {
+        enabled: bool,
+    },
+    ApproveOperator {
+        operator_id: &'a AccountId,
+    },
+    RevokeOperator {
+        operator_id: &'a AccountId,
+    },
+    GrantRole {
+        role: Role,
+        account_id: &'a AccountId,
+    },
+    RevokeRole {
+        role: Role,
+        account_id: &'a AccountId,
+    },
+    /// The code is upgraded and the state is migrated to a `state_version` layout
+    Migrate {
+        state_version: u8,
+    },
+    /// The ownership is transferred once the proposed owner accepts it
+    ProposeOwner {
+        owner_id: &'a AccountId,
+        proposed_owner_id: &'a AccountId,
+    },
+    AcceptOwner {
+        old_owner_id: &'a AccountId,
+        new_owner_id: &'a AccountId,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: AmmEvent<'a>,
+}
+
+impl AmmEvent<'_> {
+    pub fn emit(self) {
+        let log = EventLog {
+            standard: STANDARD,
+            version: VERSION,
+            event: self,
+        };
+        let json = serde_json::to_string(&log).unwrap_or_else(|_| env::abort());
+        env::log_str(&format!("EVENT_JSON:{json}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils;
+
+    #[test]
+    fn test_event_format() {
+        let account_id: AccountId = "alice.near".parse().unwrap();
+        let token_id: AccountId = "token-a.near".parse().unwrap();
+        AmmEvent::Deposit {
+            account_id: &account_id,
+            token_id: &token_id,
+            amount: 100.into(),
+        }
+        .emit();
+        AmmEvent::SetFee { fee: 30 }.emit();
+
+        assert_eq!(
+            vec![
+                r#"EVENT_JSON:{"standard":"amm","version":"1.0.0","event":"deposit","data":{"account_id":"alice.near","token_id":"token-a.near","amount":"100"}}"#,
+                r#"EVENT_JSON:{"standard":"amm","version":"1.0.0","event":"set_fee","data":{"fee":30}}"#,
+            ],
+            test_utils::get_logs()
+        );
+    }
+}
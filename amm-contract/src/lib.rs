@@ -1,15 +1,36 @@
+mod concentrated;
+mod curve;
+mod error;
+mod events;
+mod migration;
+mod oracle;
+mod pause;
+mod roles;
+mod storage;
 mod token;
+mod weighted;
 
 use near_contract_standards::fungible_token::{
-    metadata::FungibleTokenMetadata, receiver::FungibleTokenReceiver,
+    events::{FtBurn, FtMint},
+    metadata::FungibleTokenMetadata,
+    receiver::FungibleTokenReceiver,
 };
 
+use concentrated::{ConcentratedPool, PositionView};
+use curve::Curve;
+use error::AmmError;
+use events::AmmEvent;
 use near_sdk::{
+    assert_one_yocto,
     borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, UnorderedSet},
     json_types::U128,
-    AccountId, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
+    AccountId, Gas, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
 };
 use near_sdk::{env, ext_contract, log, near_bindgen};
+use oracle::Oracle;
+use pause::{Operation, PauseState};
+use roles::{Role, Roles};
 use serde::{Deserialize, Serialize};
 use token::Token;
 
@@ -22,20 +43,69 @@ pub trait ExtSelf {
         user_account_id: AccountId,
         amount: U128,
     );
+    fn on_route_hop(
+        &mut self,
+        receiver_id: AccountId,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount_in: U128,
+        amount_out: U128,
+        protocol_fee_amount: U128,
+    ) -> U128;
 }
 
 #[ext_contract(ext_ft)]
 pub trait FtToken {
     fn ft_metadata() -> FungibleTokenMetadata;
     fn ft_transfer(receiver_id: AccountId, amount: U128);
+    fn ft_transfer_call(
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> U128;
 }
 
+/// Maximum number of pools in a route of a multi-hop swap
+const MAX_ROUTE_HOPS: usize = 4;
+/// Gas that is attached to `migrate` after a code upgrade
+const GAS_FOR_MIGRATE: Gas = Gas(50_000_000_000_000);
+/// Allowed deviation of a top-up from proportions of the pool in basis points
+const DEFAULT_LIQUIDITY_TOLERANCE: u32 = 100;
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct AmmContract {
     owner_id: AccountId,
+    /// Account that becomes the owner once it accepts the ownership
+    proposed_owner_id: Option<AccountId>,
     token_a: Token,
     token_b: Token,
+    /// Ledger of LP shares, minted to liquidity providers
+    shares: Token,
+    /// Swap fee in basis points, it stays in the pool and raises a value of LP shares
+    fee: u32,
+    /// Part of the swap fee in basis points that is accrued for the protocol
+    protocol_fee: u32,
+    /// Account that receives accrued protocol fees
+    treasury_id: AccountId,
+    /// Pricing function of the pool
+    curve: Curve,
+    /// State of positions and ticks, it exists only for the concentrated liquidity curve
+    concentrated: Option<ConcentratedPool>,
+    /// Accumulated prices of tokens for time-weighted averages
+    oracle: Oracle,
+    /// Emergency switches of operations
+    pause: PauseState,
+    /// Accounts that are granted roles besides the owner
+    roles: Roles,
+    /// Accounts that are approved by the owner to act with its privileges
+    operators: UnorderedSet<AccountId>,
+    /// Allowed deviation of a top-up from proportions of the pool in basis points
+    liquidity_tolerance: u32,
+    /// Numbers of unresolved outgoing transfers of accounts, an account can't be unregistered
+    /// while any of them is pending, so a failed transfer is always credited back
+    pending_transfers: LookupMap<AccountId, u32>,
 }
 
 #[near_bindgen]
@@ -46,28 +116,400 @@ pub enum TokenType {
     B,
 }
 
+/// Action that is passed as a `msg` of `ft_transfer_call`,
+/// an empty `msg` just deposits tokens to the sender's internal balance
+///
+/// # Examples
+/// ```json
+/// {"action":"swap","to":"token-b.near","min_out":"100"}
+/// ```
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "action", rename_all = "snake_case")]
+pub enum TransferMessage {
+    /// Swaps transferred tokens and sends the output back to the sender
+    Swap {
+        to: AccountId,
+        min_out: Option<U128>,
+    },
+    /// Swaps transferred tokens through a `path` of pools, starting from this pool.
+    /// Only the output of the last hop is checked against `min_out`,
+    /// if any hop fails all previous hops are rolled back.
+    ///
+    /// ```json
+    /// {"action":"route","path":[{"pool_id":"pool-ab.near","to":"token-b.near"},
+    ///     {"pool_id":"pool-bc.near","to":"token-c.near"}],"min_out":"100"}
+    /// ```
+    Route {
+        path: Vec<RouteHop>,
+        min_out: Option<U128>,
+        /// Account that receives the output, it's the sender of the first hop by default
+        receiver_id: Option<AccountId>,
+    },
+}
+
+/// Expected result of a swap, it's calculated by the same code as the swap itself
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+    pub amount_in: U128,
+    pub amount_out: U128,
+    /// Part of the `amount_in` that is charged as a swap fee
+    pub fee: U128,
+    /// Price of one source token in destination tokens before the swap, as a decimal string
+    pub spot_price_before: String,
+    /// Price of one source token in destination tokens after the swap
+    pub spot_price_after: String,
+    /// Relative change of the spot price in basis points
+    pub price_impact: u32,
+}
+
+/// Summary of the pool that is returned by [AmmContract::pool_info]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolInfo {
+    pub token_a: TokenInfo,
+    pub token_b: TokenInfo,
+    /// Price of one token A in tokens B, see [AmmContract::exchange_rate],
+    /// it's empty until the metadata of both tokens is fetched
+    pub exchange_rate: Option<String>,
+    pub fee: u32,
+    pub protocol_fee: u32,
+    pub treasury_id: AccountId,
+    /// Total amount of minted LP shares
+    pub total_shares: U128,
+    pub owner_id: AccountId,
+}
+
+/// Pool token, its symbol and decimals are empty until the metadata is fetched
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenInfo {
+    pub id: AccountId,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    /// Balance of the token in the pool
+    pub reserve: U128,
+}
+
+/// Single swap of a route, tokens are swapped in a `pool_id` to a `to` token
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RouteHop {
+    pub pool_id: AccountId,
+    pub to: AccountId,
+}
+
 #[near_bindgen]
 impl AmmContract {
     #[init]
-    pub fn initialize(owner_id: AccountId, token_a_id: AccountId, token_b_id: AccountId) -> Self {
+    pub fn initialize(
+        owner_id: AccountId,
+        token_a_id: AccountId,
+        token_b_id: AccountId,
+        fee: u32,
+        curve: Option<Curve>,
+    ) -> Self {
+        assert_valid_fee(fee);
+        let curve = curve.unwrap_or_default();
+        curve.assert_valid();
+
         // Let's fetch a metadata for a provided tokens
         metadata(token_a_id.clone(), TokenType::A);
         metadata(token_b_id.clone(), TokenType::B);
 
+        let concentrated = match curve {
+            Curve::Concentrated {
+                tick_spacing,
+                initial_tick,
+            } => Some(ConcentratedPool::new(tick_spacing, initial_tick)),
+            _ => None,
+        };
+
+        migration::write_state_version();
         Self {
+            treasury_id: owner_id.clone(),
             owner_id,
+            proposed_owner_id: None,
             token_a: Token::new(token_a_id, b"a".to_vec()),
             token_b: Token::new(token_b_id, b"b".to_vec()),
+            shares: Token::new(env::current_account_id(), b"s".to_vec()),
+            fee,
+            protocol_fee: 0,
+            curve,
+            concentrated,
+            oracle: Oracle::new(),
+            pause: PauseState::default(),
+            roles: Roles::new(b"r".to_vec()),
+            operators: UnorderedSet::new(b"d".to_vec()),
+            liquidity_tolerance: DEFAULT_LIQUIDITY_TOLERANCE,
+            pending_transfers: LookupMap::new(b"w".to_vec()),
+        }
+    }
+
+    /// Deploys a new code that is passed as a raw input and migrates the state,
+    /// available only for admins
+    pub fn upgrade(&self) -> Promise {
+        self.assert_role(Role::Admin);
+        let code = env::input().unwrap_or_else(|| env::panic_str("Contract code is empty"));
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), vec![], 0, GAS_FOR_MIGRATE)
+    }
+
+    /// Converts the stored state of any known layout to the current one,
+    /// it's called by [Self::upgrade] after a deployment of a new code
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract: Self = migration::VersionedState::read().into();
+        migration::write_state_version();
+        AmmEvent::Migrate {
+            state_version: migration::STATE_VERSION,
+        }
+        .emit();
+        contract
+    }
+
+    /// Version of the stored layout of the state
+    pub fn state_version(&self) -> u8 {
+        migration::state_version()
+    }
+
+    /// Proposes a `new_owner_id`, that should call [Self::accept_owner] to become the owner.
+    /// Available only for the owner, a new proposal replaces the previous one.
+    pub fn propose_owner(&mut self, new_owner_id: AccountId) {
+        self.assert_owner();
+        AmmEvent::ProposeOwner {
+            owner_id: &self.owner_id,
+            proposed_owner_id: &new_owner_id,
         }
+        .emit();
+        self.proposed_owner_id = Some(new_owner_id);
+    }
+
+    /// Transfers the ownership to a caller, available only for the proposed owner.
+    /// Operators of the previous owner are revoked.
+    pub fn accept_owner(&mut self) {
+        let new_owner_id = env::predecessor_account_id();
+        if self.proposed_owner_id.as_ref() != Some(&new_owner_id) {
+            env::panic_str(format!("The account {new_owner_id} isn't a proposed owner").as_str());
+        }
+
+        AmmEvent::AcceptOwner {
+            old_owner_id: &self.owner_id,
+            new_owner_id: &new_owner_id,
+        }
+        .emit();
+        self.owner_id = new_owner_id;
+        self.proposed_owner_id = None;
+        self.operators.clear();
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_proposed_owner(&self) -> Option<AccountId> {
+        self.proposed_owner_id.clone()
+    }
+
+    /// Approves an `operator_id` to call privileged methods on behalf of the owner,
+    /// available only for the owner. Operators don't transfer the ownership.
+    pub fn approve_operator(&mut self, operator_id: AccountId) {
+        self.assert_owner();
+        if self.operators.insert(&operator_id) {
+            AmmEvent::ApproveOperator {
+                operator_id: &operator_id,
+            }
+            .emit();
+        }
+    }
+
+    /// Revokes an approval of an `operator_id`, available only for the owner
+    pub fn revoke_operator(&mut self, operator_id: AccountId) {
+        self.assert_owner();
+        if self.operators.remove(&operator_id) {
+            AmmEvent::RevokeOperator {
+                operator_id: &operator_id,
+            }
+            .emit();
+        }
+    }
+
+    pub fn get_operators(&self) -> Vec<AccountId> {
+        self.operators.to_vec()
+    }
+
+    /// Grants a `role` to an `account_id`, available only for admins
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_role(Role::Admin);
+        if self.roles.grant(role, account_id.clone()) {
+            AmmEvent::GrantRole {
+                role,
+                account_id: &account_id,
+            }
+            .emit();
+        }
+    }
+
+    /// Revokes a `role` of an `account_id`, available only for admins.
+    /// Roles of the owner couldn't be revoked.
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_role(Role::Admin);
+        if self.roles.revoke(role, account_id.clone()) {
+            AmmEvent::RevokeRole {
+                role,
+                account_id: &account_id,
+            }
+            .emit();
+        }
+    }
+
+    /// Check if an `account_id` holds a `role`. The owner holds every role and its operators
+    /// hold every role except the admin, so they can't keep any access once they are revoked.
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        account_id == self.owner_id
+            || (role != Role::Admin && self.operators.contains(&account_id))
+            || self.roles.has_role(role, &account_id)
+    }
+
+    /// Accounts that are granted a `role`, the owner isn't listed
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.roles.members(role)
     }
 
+    /// Changes the swap fee, available only for fee managers
+    pub fn set_fee(&mut self, fee: u32) {
+        self.assert_role(Role::FeeManager);
+        assert_valid_fee(fee);
+        self.fee = fee;
+        AmmEvent::SetFee { fee }.emit();
+    }
+
+    pub fn fee(&self) -> u32 {
+        self.fee
+    }
+
+    /// Changes the protocol part of the swap fee, available only for fee managers
+    pub fn set_protocol_fee(&mut self, protocol_fee: u32) {
+        self.assert_role(Role::FeeManager);
+        assert_valid_fee(protocol_fee);
+        self.protocol_fee = protocol_fee;
+        AmmEvent::SetProtocolFee { protocol_fee }.emit();
+    }
+
+    pub fn protocol_fee(&self) -> u32 {
+        self.protocol_fee
+    }
+
+    /// Changes the account that receives protocol fees, available only for admins.
+    /// The treasury should be registered with `storage_deposit` beforehand.
+    pub fn set_treasury(&mut self, treasury_id: AccountId) {
+        self.assert_role(Role::Admin);
+        self.assert_registered(&treasury_id);
+        AmmEvent::SetTreasury {
+            treasury_id: &treasury_id,
+        }
+        .emit();
+        self.treasury_id = treasury_id;
+    }
+
+    pub fn treasury(&self) -> AccountId {
+        self.treasury_id.clone()
+    }
+
+    /// Changes the amplification coefficient of a StableSwap pool,
+    /// available only for liquidity managers
+    pub fn set_amp(&mut self, amp: u64) {
+        self.assert_role(Role::LiquidityManager);
+        curve::assert_valid_amp(amp);
+        match &mut self.curve {
+            Curve::StableSwap { amp: current_amp } => *current_amp = amp,
+            _ => env::panic_str("Pool curve doesn't have an amplification"),
+        }
+        AmmEvent::SetAmp { amp }.emit();
+    }
+
+    pub fn curve(&self) -> Curve {
+        self.curve.clone()
+    }
+
+    /// Changes the allowed deviation of a top-up from proportions of the pool,
+    /// available only for liquidity managers
+    pub fn set_liquidity_tolerance(&mut self, tolerance: u32) {
+        self.assert_role(Role::LiquidityManager);
+        if tolerance > token::FEE_DIVISOR {
+            env::panic_str(
+                format!(
+                    "Tolerance {tolerance} should not be greater than {}",
+                    token::FEE_DIVISOR
+                )
+                .as_str(),
+            );
+        }
+        self.liquidity_tolerance = tolerance;
+        AmmEvent::SetLiquidityTolerance { tolerance }.emit();
+    }
+
+    pub fn liquidity_tolerance(&self) -> u32 {
+        self.liquidity_tolerance
+    }
+
+    /// Pauses or resumes an `operation`, available only for pausers
+    pub fn set_paused(&mut self, operation: Operation, paused: bool) {
+        self.assert_role(Role::Pauser);
+        self.pause.set(operation, paused);
+        AmmEvent::SetPaused { operation, paused }.emit();
+    }
+
+    /// Enables or disables the emergency mode that pauses everything except withdrawals,
+    /// available only for pausers
+    pub fn set_withdraw_only(&mut self, enabled: bool) {
+        self.assert_role(Role::Pauser);
+        self.pause.withdraw_only = enabled;
+        AmmEvent::SetWithdrawOnly { enabled }.emit();
+    }
+
+    pub fn pause_state(&self) -> PauseState {
+        self.pause.clone()
+    }
+
+    /// Credits accrued protocol fees of both tokens to the treasury internal balances.
+    /// Could be called by anyone, fees always go to the treasury, that should be registered.
+    pub fn claim_protocol_fees(&mut self) -> (U128, U128) {
+        self.assert_registered(&self.treasury_id);
+        let amount_a = self.token_a.claim_protocol_fees(&self.treasury_id);
+        let amount_b = self.token_b.claim_protocol_fees(&self.treasury_id);
+        AmmEvent::ClaimProtocolFees {
+            treasury_id: &self.treasury_id,
+            amount_a,
+            amount_b,
+        }
+        .emit();
+        (amount_a, amount_b)
+    }
+
+    pub fn token_a_protocol_fees(&self) -> U128 {
+        self.token_a.protocol_fees.into()
+    }
+
+    pub fn token_b_protocol_fees(&self) -> U128 {
+        self.token_b.protocol_fees.into()
+    }
+
+    /// Moves amounts of tokens A and B from a caller's internal balances to the pool and mints
+    /// LP shares. A top-up should be proportional to the pool balances up to the liquidity
+    /// tolerance. With `optimal`, amounts are maximums, the largest proportional amounts are
+    /// deposited and the excess is left in the caller's balances.
     pub fn add_liquidity(
         &mut self,
         token_a_id: AccountId,
         amount_liq_a: U128,
         token_b_id: AccountId,
         amount_liq_b: U128,
-    ) {
+        optimal: Option<bool>,
+    ) -> U128 {
+        self.pause.assert_not_paused(Operation::AddLiquidity);
         // Supports only known tokens
         if token_a_id != self.token_a.id || token_b_id != self.token_b.id {
             env::panic_str(
@@ -80,16 +522,15 @@ impl AmmContract {
             )
         }
 
-        let contract_id = env::current_account_id();
-        let owner_id = env::predecessor_account_id();
-        let signer_account_id = env::signer_account_id();
+        self.update_oracle();
 
-        if self.owner_id != owner_id && self.owner_id != signer_account_id {
-            env::panic_str("Access unauthorized");
-        }
+        // Any account that holds internal balances can provide a liquidity
+        let contract_id = env::current_account_id();
+        let provider_id = env::predecessor_account_id();
+        self.assert_registered(&provider_id);
 
-        let token_a_balance = self.token_a.balance_of(owner_id.clone());
-        let token_b_balance = self.token_b.balance_of(owner_id.clone());
+        let token_a_balance = self.token_a.balance_of(provider_id.clone());
+        let token_b_balance = self.token_b.balance_of(provider_id.clone());
         if token_a_balance < amount_liq_a || token_b_balance < amount_liq_b {
             env::panic_str(
                 format!(
@@ -103,63 +544,196 @@ impl AmmContract {
 
         let liq_balance_a = self.token_a.balance_of(contract_id.clone());
         let liq_balance_b = self.token_b.balance_of(contract_id.clone());
+        let is_first_deposit = liq_balance_a == 0.into() && liq_balance_b == 0.into();
+        let (amount_liq_a, amount_liq_b) = if is_first_deposit {
+            (amount_liq_a, amount_liq_b)
+        } else {
+            self.top_up_amounts(
+                liq_balance_a,
+                liq_balance_b,
+                amount_liq_a,
+                amount_liq_b,
+                optimal.unwrap_or(false),
+            )
+        };
+
+        let shares = self.curve.shares(
+            liq_balance_a,
+            liq_balance_b,
+            amount_liq_a,
+            amount_liq_b,
+            self.shares.total_supply(),
+        );
+        if shares == 0.into() {
+            env::panic_str("Provided liquidity is too small to mint shares");
+        }
 
-        if liq_balance_a == 0.into() && liq_balance_b == 0.into() {
+        if is_first_deposit {
             // At the first call, tokens don't have a contract accounts
             self.token_a.try_register_account(&contract_id);
             self.token_b.try_register_account(&contract_id);
-            self.token_a.transfer(&owner_id, &contract_id, amount_liq_a);
-            self.token_b.transfer(&owner_id, &contract_id, amount_liq_b);
-        } else {
-            let exchange_rate = liq_balance_a.0.checked_div(liq_balance_b.0).unwrap();
-            let amount_check = amount_liq_a.0.checked_mul(exchange_rate).unwrap();
-
-            if amount_liq_b > amount_check.into() {
-                env::panic_str("Incorrect amounts for top up a liquidity")
-            }
+        }
+        self.token_a
+            .transfer(&provider_id, &contract_id, amount_liq_a);
+        self.token_b
+            .transfer(&provider_id, &contract_id, amount_liq_b);
 
-            self.token_a.transfer(&owner_id, &contract_id, amount_liq_a);
-            self.token_b.transfer(&owner_id, &contract_id, amount_liq_b);
+        self.shares.deposit(&provider_id, shares);
+        FtMint {
+            owner_id: &provider_id,
+            amount: &shares,
+            memo: None,
+        }
+        .emit();
+        AmmEvent::AddLiquidity {
+            account_id: &provider_id,
+            amount_a: amount_liq_a,
+            amount_b: amount_liq_b,
+            shares,
         }
+        .emit();
+        shares
     }
 
-    pub fn swap(&mut self, from_token_id: AccountId, to_token_id: AccountId, amount: U128) {
+    /// Burns `shares` of a caller and credits proportional amounts of tokens A and B
+    /// to the caller's internal balances
+    pub fn remove_liquidity(
+        &mut self,
+        shares: U128,
+        min_amount_a: U128,
+        min_amount_b: U128,
+    ) -> (U128, U128) {
         let contract_id = env::current_account_id();
         let user_account_id = env::predecessor_account_id();
 
-        if self
-            .token(&from_token_id)
-            .balance_of(user_account_id.clone())
-            < amount
-        {
+        let user_shares = self.shares.balance_of(user_account_id.clone());
+        if shares == 0.into() || user_shares < shares {
+            env::panic_str(
+                format!(
+                    "Incorrect amount of shares to remove {shares:?}, \
+                    User shares: {user_shares:?}"
+                )
+                .as_str(),
+            );
+        }
+
+        self.update_oracle();
+        let total_shares = self.shares.total_supply();
+        let liq_balance_a = self.token_a.balance_of(contract_id.clone());
+        let liq_balance_b = self.token_b.balance_of(contract_id.clone());
+
+        let amount_a: U128 = token::mul_div(liq_balance_a.0, shares.0, total_shares.0).into();
+        let amount_b: U128 = token::mul_div(liq_balance_b.0, shares.0, total_shares.0).into();
+
+        if amount_a == 0.into() || amount_b == 0.into() {
+            env::panic_str("Amount of shares is too small to remove liquidity");
+        }
+
+        if amount_a < min_amount_a || amount_b < min_amount_b {
             env::panic_str(
-                format!("The user {} doesn't have enough funds", &user_account_id).as_str(),
+                format!(
+                    "Removed liquidity is less than expected, \
+                    Token A amount: {amount_a:?}, \
+                    Token B amount: {amount_b:?}"
+                )
+                .as_str(),
             );
         }
 
-        // Get current statement of pool
-        let src_pool_balance = self.token(&from_token_id).balance_of(contract_id.clone());
-        let dst_pool_balance = self.token(&to_token_id).balance_of(contract_id.clone());
+        self.shares.withdraw(&user_account_id, shares);
+        FtBurn {
+            owner_id: &user_account_id,
+            amount: &shares,
+            memo: None,
+        }
+        .emit();
 
-        if src_pool_balance == 0.into() || dst_pool_balance == 0.into() {
-            env::panic_str("Pool balance couldn't be equal to 0");
+        self.token_a
+            .transfer(&contract_id, &user_account_id, amount_a);
+        self.token_b
+            .transfer(&contract_id, &user_account_id, amount_b);
+        AmmEvent::RemoveLiquidity {
+            account_id: &user_account_id,
+            amount_a,
+            amount_b,
+            shares,
         }
+        .emit();
+
+        (amount_a, amount_b)
+    }
 
-        self.token(&from_token_id)
-            .transfer(&user_account_id, &contract_id, amount);
+    /// Swaps an `amount` of `from_token_id` to `to_token_id`.
+    /// Fails if the received amount is less than an optional `min_amount_out`.
+    pub fn swap(
+        &mut self,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount: U128,
+        min_amount_out: Option<U128>,
+    ) -> U128 {
+        self.pause.assert_not_paused(Operation::Swap);
+        let user_account_id = env::predecessor_account_id();
+        self.assert_registered(&user_account_id);
+        let amount_to_transfer = self.calc_amount_out(&from_token_id, &to_token_id, amount);
 
-        let amount_to_transfer =
-            token::calc_transfer_amount(src_pool_balance, dst_pool_balance, amount);
+        if let Some(min_amount_out) = min_amount_out {
+            if amount_to_transfer < min_amount_out {
+                AmmError::SlippageExceeded {
+                    amount_out: amount_to_transfer,
+                    min_amount_out,
+                }
+                .panic();
+            }
+        }
 
-        // In case if other token wallet not used yet
-        self.token(&to_token_id)
-            .try_register_account(&user_account_id);
-        self.token(&to_token_id)
-            .transfer(&contract_id, &user_account_id, amount_to_transfer);
+        self.apply_concentrated_swap(&from_token_id, amount, true);
+        self.internal_swap(
+            &user_account_id,
+            &from_token_id,
+            &to_token_id,
+            amount,
+            amount_to_transfer,
+        );
+        amount_to_transfer
+    }
+
+    /// Swaps `from_token_id` to exactly `amount_out` of `to_token_id`.
+    /// Fails if the required input amount is greater than `max_amount_in`.
+    pub fn swap_exact_out(
+        &mut self,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount_out: U128,
+        max_amount_in: U128,
+    ) -> U128 {
+        self.pause.assert_not_paused(Operation::Swap);
+        let user_account_id = env::predecessor_account_id();
+        self.assert_registered(&user_account_id);
+        let amount_in = self.calc_amount_in(&from_token_id, &to_token_id, amount_out);
+
+        if amount_in > max_amount_in {
+            AmmError::ExcessiveAmountIn {
+                amount_in,
+                max_amount_in,
+            }
+            .panic();
+        }
+
+        self.apply_concentrated_swap(&to_token_id, amount_out, false);
+        self.internal_swap(
+            &user_account_id,
+            &from_token_id,
+            &to_token_id,
+            amount_in,
+            amount_out,
+        );
+        amount_in
     }
 
     #[payable]
     pub fn withdraw_token(&mut self, token_id: AccountId, amount: U128) -> Promise {
+        self.pause.assert_not_paused(Operation::Withdraw);
         let user_account_id = env::predecessor_account_id();
         let user_balance = self.token(&token_id).balance_of(user_account_id.clone());
 
@@ -173,6 +747,15 @@ impl AmmContract {
             )
         }
 
+        // The balance is debited before the transfer, so it can't be spent while it's pending
+        self.token(&token_id).withdraw(&user_account_id, amount);
+        self.start_pending_transfer(&user_account_id);
+        AmmEvent::WithdrawStarted {
+            account_id: &user_account_id,
+            token_id: &token_id,
+            amount,
+        }
+        .emit();
         ext_ft::ext(token_id.clone())
             .with_attached_deposit(1)
             .ft_transfer(user_account_id.clone(), amount)
@@ -197,6 +780,66 @@ impl AmmContract {
             .unwrap_or_else(|| env::panic_str("Metadata for a token B is empty"))
     }
 
+    /// Price of one token A in tokens B, as a decimal string that takes decimals
+    /// and weights of tokens into account
+    pub fn exchange_rate(&self) -> String {
+        let (numerator, denominator) = self.price_ratio();
+        token::format_ratio(numerator, denominator)
+    }
+
+    /// Tokens, reserves, the exchange rate and settings of the pool in a single view
+    pub fn pool_info(&self) -> PoolInfo {
+        PoolInfo {
+            token_a: token_info(&self.token_a),
+            token_b: token_info(&self.token_b),
+            exchange_rate: self.has_metadata().then(|| self.exchange_rate()),
+            fee: self.fee,
+            protocol_fee: self.protocol_fee,
+            treasury_id: self.treasury_id.clone(),
+            total_shares: self.shares.total_supply(),
+            owner_id: self.owner_id.clone(),
+        }
+    }
+
+    /// Quotes a swap of an `amount` of `from_token_id` to `to_token_id`, see [Self::swap]
+    pub fn get_amount_out(
+        &self,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount: U128,
+    ) -> Quote {
+        let amount_out = self.calc_amount_out(&from_token_id, &to_token_id, amount);
+        self.quote(&from_token_id, &to_token_id, amount, amount_out, true)
+    }
+
+    /// Quotes a swap of `from_token_id` to exactly `amount_out` of `to_token_id`,
+    /// see [Self::swap_exact_out]
+    pub fn get_amount_in(
+        &self,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount_out: U128,
+    ) -> Quote {
+        let amount_in = self.calc_amount_in(&from_token_id, &to_token_id, amount_out);
+        self.quote(&from_token_id, &to_token_id, amount_in, amount_out, false)
+    }
+
+    /// Time-weighted average price of one `token_id` in another token for the last
+    /// `window_seconds`, as a decimal string like [Self::exchange_rate]
+    pub fn consult(&self, token_id: AccountId, window_seconds: u64) -> String {
+        let token_a = match token_id {
+            id if id == self.token_a.id => true,
+            id if id == self.token_b.id => false,
+            _ => env::panic_str(format!("Doesn't support passed token_id {token_id}").as_str()),
+        };
+
+        let (price_a, price_b) = self.spot_prices();
+        let price = self
+            .oracle
+            .consult(price_a, price_b, token_a, window_seconds);
+        token::format_ratio(price, concentrated::Q64)
+    }
+
     pub fn token_a_supply(&self) -> U128 {
         self.token_a.total_supply()
     }
@@ -221,6 +864,783 @@ impl AmmContract {
         self.token_b.balance_of(account_id)
     }
 
+    /// Transfers LP shares to a `receiver_id`, follows the NEP-141 `ft_transfer`
+    #[payable]
+    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+
+        self.assert_registered(&receiver_id);
+        self.shares
+            .internal_token
+            .internal_transfer(&sender_id, &receiver_id, amount.into(), memo);
+    }
+
+    /// Total amount of minted LP shares
+    pub fn ft_total_supply(&self) -> U128 {
+        self.shares.total_supply()
+    }
+
+    /// Amount of LP shares owned by an `account_id`
+    pub fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.shares.balance_of(account_id)
+    }
+
+    /// Opens a concentrated liquidity position of a caller in a range from `lower_tick`
+    /// to `upper_tick`, at most `amount_a` and `amount_b` are taken from internal balances.
+    /// Returns an id of the position.
+    pub fn open_position(
+        &mut self,
+        lower_tick: i32,
+        upper_tick: i32,
+        amount_a: U128,
+        amount_b: U128,
+    ) -> u64 {
+        self.pause.assert_not_paused(Operation::AddLiquidity);
+        let contract_id = env::current_account_id();
+        let provider_id = env::predecessor_account_id();
+        self.assert_registered(&provider_id);
+
+        self.update_oracle();
+        let precision = self.precision();
+        let normalized_a = self.token_a.normalize(amount_a, precision);
+        let normalized_b = self.token_b.normalize(amount_b, precision);
+        let (position_id, normalized_a, normalized_b) = self.concentrated_pool_mut().open_position(
+            provider_id.clone(),
+            lower_tick,
+            upper_tick,
+            normalized_a,
+            normalized_b,
+        );
+
+        // Required amounts are rounded up, so the pool always covers positions
+        let deposit_a = self.token_a.denormalize_ceil(normalized_a, precision);
+        let deposit_b = self.token_b.denormalize_ceil(normalized_b, precision);
+        let token_a_balance = self.token_a.balance_of(provider_id.clone());
+        let token_b_balance = self.token_b.balance_of(provider_id.clone());
+        if token_a_balance < deposit_a || token_b_balance < deposit_b {
+            env::panic_str(
+                format!(
+                    "Not enough balance to open a position, \
+                    Token A balance: {token_a_balance:?}, \
+                    Token B balance: {token_b_balance:?}"
+                )
+                .as_str(),
+            );
+        }
+
+        self.token_a.try_register_account(&contract_id);
+        self.token_b.try_register_account(&contract_id);
+        self.transfer_pair(&provider_id, &contract_id, deposit_a, deposit_b);
+        AmmEvent::OpenPosition {
+            account_id: &provider_id,
+            position_id,
+            lower_tick,
+            upper_tick,
+            amount_a: deposit_a,
+            amount_b: deposit_b,
+        }
+        .emit();
+        position_id
+    }
+
+    /// Closes a position of a caller, its liquidity and accrued fees are credited
+    /// to the caller's internal balances
+    pub fn close_position(&mut self, position_id: u64) -> (U128, U128) {
+        let user_account_id = env::predecessor_account_id();
+        self.assert_registered(&user_account_id);
+        self.update_oracle();
+        let amounts = self
+            .concentrated_pool_mut()
+            .close_position(&user_account_id, position_id);
+        let (amount_a, amount_b) = self.withdraw_position_amounts(&user_account_id, amounts);
+        AmmEvent::ClosePosition {
+            account_id: &user_account_id,
+            position_id,
+            amount_a,
+            amount_b,
+        }
+        .emit();
+        (amount_a, amount_b)
+    }
+
+    /// Credits accrued fees of a caller's position to the caller's internal balances
+    pub fn collect_fees(&mut self, position_id: u64) -> (U128, U128) {
+        let user_account_id = env::predecessor_account_id();
+        self.assert_registered(&user_account_id);
+        let amounts = self
+            .concentrated_pool_mut()
+            .collect_fees(&user_account_id, position_id);
+        let (amount_a, amount_b) = self.withdraw_position_amounts(&user_account_id, amounts);
+        AmmEvent::CollectFees {
+            account_id: &user_account_id,
+            position_id,
+            amount_a,
+            amount_b,
+        }
+        .emit();
+        (amount_a, amount_b)
+    }
+
+    /// Concentrated liquidity position, accrued fees are in decimals of tokens
+    pub fn get_position(&self, position_id: u64) -> Option<PositionView> {
+        let precision = self.precision();
+        self.concentrated_pool()
+            .position(position_id)
+            .map(|position| PositionView {
+                fees_a: self.token_a.denormalize(position.fees_a.0, precision),
+                fees_b: self.token_b.denormalize(position.fees_b.0, precision),
+                ..position
+            })
+    }
+
+    /// Tick of the current price of concentrated liquidity
+    pub fn current_tick(&self) -> i32 {
+        self.concentrated_pool().tick
+    }
+
+    /// Moves normalized amounts of a position from the pool to a user, rounding down
+    fn withdraw_position_amounts(
+        &mut self,
+        user_account_id: &AccountId,
+        amounts: (u128, u128),
+    ) -> (U128, U128) {
+        let contract_id = env::current_account_id();
+        let precision = self.precision();
+        let (amount_a, amount_b) = amounts;
+        let amount_a = self.token_a.denormalize(amount_a, precision);
+        let amount_b = self.token_b.denormalize(amount_b, precision);
+
+        self.transfer_pair(&contract_id, user_account_id, amount_a, amount_b);
+        (amount_a, amount_b)
+    }
+
+    /// Only the direct caller is checked, so a contract called by the owner can't act as the owner
+    fn assert_owner(&self) {
+        if self.owner_id != env::predecessor_account_id() {
+            env::panic_str("Access unauthorized");
+        }
+    }
+
+    /// The owner passes any role check and its operators any but the admin one, other callers
+    /// should be granted the `role`
+    fn assert_role(&self, role: Role) {
+        let predecessor_account_id = env::predecessor_account_id();
+        if !self.has_role(role, predecessor_account_id) {
+            env::panic_str(format!("Access unauthorized, {role:?} role is required").as_str());
+        }
+    }
+
+    /// Counts a transfer to an `account_id` that is resolved by a callback
+    fn start_pending_transfer(&mut self, account_id: &AccountId) {
+        let count = self.pending_transfers.get(account_id).unwrap_or(0);
+        self.pending_transfers.insert(account_id, &(count + 1));
+    }
+
+    /// Resolves a transfer that is counted by [Self::start_pending_transfer]
+    fn finish_pending_transfer(&mut self, account_id: &AccountId) {
+        match self.pending_transfers.get(account_id).unwrap_or(0) {
+            0 | 1 => {
+                self.pending_transfers.remove(account_id);
+            }
+            count => {
+                self.pending_transfers.insert(account_id, &(count - 1));
+            }
+        }
+    }
+
+    /// Swaps transferred tokens and sends the output to the receiver with `ft_transfer`.
+    /// Returns the whole `amount` of a swap that isn't possible, so it's refunded. Limits of
+    /// curves still panic, then the token contract refunds the amount of the failed call.
+    fn swap_on_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount: U128,
+        min_amount_out: Option<U128>,
+    ) -> PromiseOrValue<U128> {
+        // The output returns to the internal balance if its transfer fails,
+        // so the receiver should pay for the storage beforehand
+        if !self.is_registered(&receiver_id) {
+            log!("The account {} is not registered", receiver_id);
+            return PromiseOrValue::Value(amount);
+        }
+
+        let amount_out = match self.transfer_amount_out(&from_token_id, &to_token_id, amount) {
+            Some(amount_out) => amount_out,
+            None => return PromiseOrValue::Value(amount),
+        };
+        if amount_out < min_amount_out.unwrap_or(amount_out) {
+            log!(
+                "Swap output {:?} is less than expected {:?}",
+                amount_out,
+                min_amount_out
+            );
+            return PromiseOrValue::Value(amount);
+        }
+
+        self.token(&from_token_id).deposit(&receiver_id, amount);
+        AmmEvent::Deposit {
+            account_id: &receiver_id,
+            token_id: &from_token_id,
+            amount,
+        }
+        .emit();
+        self.apply_concentrated_swap(&from_token_id, amount, true);
+        self.internal_swap(
+            &receiver_id,
+            &from_token_id,
+            &to_token_id,
+            amount,
+            amount_out,
+        );
+
+        // The output stays on the internal balance if withdrawals are paused
+        if self.pause.is_paused(Operation::Withdraw) {
+            log!("Withdrawals are paused, the output is kept on the internal balance");
+            return PromiseOrValue::Value(0.into());
+        }
+        self.token(&to_token_id).withdraw(&receiver_id, amount_out);
+        self.start_pending_transfer(&receiver_id);
+        AmmEvent::WithdrawStarted {
+            account_id: &receiver_id,
+            token_id: &to_token_id,
+            amount: amount_out,
+        }
+        .emit();
+        ext_ft::ext(to_token_id.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(receiver_id.clone(), amount_out)
+            .then(
+                ext_self::ext(env::current_account_id()).withdraw_token_callback(
+                    to_token_id,
+                    receiver_id,
+                    amount_out,
+                ),
+            );
+
+        PromiseOrValue::Value(0.into())
+    }
+
+    /// Makes the first hop of a `path` in this pool and passes the output to the next pool
+    /// with `ft_transfer_call`, the last hop sends the output to the receiver.
+    /// Returns the whole `amount` of a hop that isn't possible, so it's refunded and the previous
+    /// pool of the route rolls back its hop. Limits of curves still panic, then the token
+    /// contract refunds the amount of the failed call and the previous hop is rolled back as well.
+    fn route_on_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        from_token_id: AccountId,
+        mut path: Vec<RouteHop>,
+        amount: U128,
+        min_amount_out: Option<U128>,
+    ) -> PromiseOrValue<U128> {
+        let contract_id = env::current_account_id();
+        if path.is_empty() || path.len() > MAX_ROUTE_HOPS || path[0].pool_id != contract_id {
+            log!(
+                "Route should start with {} and have at most {} hops",
+                contract_id,
+                MAX_ROUTE_HOPS
+            );
+            return PromiseOrValue::Value(amount);
+        }
+
+        // A partially refunded output of a hop is kept for the receiver,
+        // so the receiver should pay for the storage in every pool of the route
+        if !self.is_registered(&receiver_id) {
+            log!("The account {} is not registered", receiver_id);
+            return PromiseOrValue::Value(amount);
+        }
+
+        let hop = path.remove(0);
+        if path.is_empty() {
+            return self.swap_on_transfer(
+                receiver_id,
+                from_token_id,
+                hop.to,
+                amount,
+                min_amount_out,
+            );
+        }
+
+        // A swap of concentrated liquidity moves ticks, so it couldn't be rolled back
+        if self.concentrated.is_some() {
+            log!(
+                "Pool {} with concentrated liquidity could be only the last hop of a route",
+                contract_id
+            );
+            return PromiseOrValue::Value(amount);
+        }
+
+        let amount_out = match self.transfer_amount_out(&from_token_id, &hop.to, amount) {
+            Some(amount_out) => amount_out,
+            None => return PromiseOrValue::Value(amount),
+        };
+        let protocol_fee_amount =
+            self.internal_route_swap(&from_token_id, &hop.to, amount, amount_out);
+        AmmEvent::Swap {
+            account_id: &receiver_id,
+            token_in: &from_token_id,
+            amount_in: amount,
+            token_out: &hop.to,
+            amount_out,
+            protocol_fee: protocol_fee_amount,
+        }
+        .emit();
+
+        let next_pool_id = path[0].pool_id.clone();
+        let msg = near_sdk::serde_json::to_string(&TransferMessage::Route {
+            path,
+            min_out: min_amount_out,
+            receiver_id: Some(receiver_id.clone()),
+        })
+        .unwrap();
+
+        self.start_pending_transfer(&receiver_id);
+        ext_ft::ext(hop.to.clone())
+            .with_attached_deposit(1)
+            .ft_transfer_call(next_pool_id, amount_out, None, msg)
+            .then(ext_self::ext(contract_id).on_route_hop(
+                receiver_id,
+                from_token_id,
+                hop.to,
+                amount,
+                amount_out,
+                protocol_fee_amount,
+            ))
+            .into()
+    }
+
+    /// Calculates an output of a swap of transferred tokens,
+    /// returns `None` if the swap isn't possible
+    fn transfer_amount_out(
+        &mut self,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount: U128,
+    ) -> Option<U128> {
+        if from_token_id == to_token_id
+            || (self.token_a.id != *to_token_id && self.token_b.id != *to_token_id)
+        {
+            log!("Couldn't swap {} to {}", from_token_id, to_token_id);
+            return None;
+        }
+
+        let contract_id = env::current_account_id();
+        let src_pool_balance = self.token(from_token_id).balance_of(contract_id.clone());
+        let dst_pool_balance = self.token(to_token_id).balance_of(contract_id);
+        if src_pool_balance == 0.into() || dst_pool_balance == 0.into() {
+            log!("Pool balance couldn't be equal to 0");
+            return None;
+        }
+
+        let amount_out = self.calc_amount_out(from_token_id, to_token_id, amount);
+        if amount_out == 0.into() {
+            log!("Swap output of {:?} is equal to 0", amount);
+            return None;
+        }
+
+        Some(amount_out)
+    }
+
+    /// Calculates a swap output, amounts of both tokens are normalized to the same decimals
+    fn calc_amount_out(
+        &self,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount: U128,
+    ) -> U128 {
+        let precision = self.precision();
+        if self.concentrated.is_some() {
+            let amount = self.token_ref(from_token_id).normalize(amount, precision);
+            let result = self.calc_concentrated_swap(from_token_id, amount, true);
+            return self
+                .token_ref(to_token_id)
+                .denormalize(result.amount_out, precision);
+        }
+
+        let (src_pool_balance, dst_pool_balance) = self.pool_balances(from_token_id, to_token_id);
+
+        let from_token = self.token_ref(from_token_id);
+        let src_pool_balance = from_token.normalize(src_pool_balance, precision);
+        let amount = from_token.normalize(amount, precision);
+        let dst_pool_balance = self
+            .token_ref(to_token_id)
+            .normalize(dst_pool_balance, precision);
+
+        let amount_out = self.swap_curve(from_token_id).amount_out(
+            src_pool_balance.into(),
+            dst_pool_balance.into(),
+            amount.into(),
+            self.fee,
+        );
+        self.token_ref(to_token_id)
+            .denormalize(amount_out.0, precision)
+    }
+
+    /// Calculates a swap input for an exact output, rounding is in favour of the pool
+    fn calc_amount_in(
+        &self,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount_out: U128,
+    ) -> U128 {
+        let precision = self.precision();
+        if self.concentrated.is_some() {
+            let amount_out = self.token_ref(to_token_id).normalize(amount_out, precision);
+            let result = self.calc_concentrated_swap(from_token_id, amount_out, false);
+            return self
+                .token_ref(from_token_id)
+                .denormalize_ceil(result.amount_in, precision);
+        }
+
+        let (src_pool_balance, dst_pool_balance) = self.pool_balances(from_token_id, to_token_id);
+
+        let src_pool_balance = self
+            .token_ref(from_token_id)
+            .normalize(src_pool_balance, precision);
+        let to_token = self.token_ref(to_token_id);
+        let dst_pool_balance = to_token.normalize(dst_pool_balance, precision);
+        let amount_out = to_token.normalize(amount_out, precision);
+
+        let amount_in = self.swap_curve(from_token_id).amount_in(
+            src_pool_balance.into(),
+            dst_pool_balance.into(),
+            amount_out.into(),
+            self.fee,
+        );
+        self.token_ref(from_token_id)
+            .denormalize_ceil(amount_in.0, precision)
+    }
+
+    /// Builds a quote of a swap, the spot price after the swap is calculated for pool balances
+    /// or concentrated liquidity changed the same way as [Self::internal_swap] changes them
+    fn quote(
+        &self,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount_in: U128,
+        amount_out: U128,
+        exact_in: bool,
+    ) -> Quote {
+        if from_token_id == to_token_id {
+            env::panic_str(format!("Couldn't swap {from_token_id} to {to_token_id}").as_str());
+        }
+
+        let (numerator, denominator) = self.price_ratio();
+        let (numerator_after, denominator_after) = if self.concentrated.is_some() {
+            let precision = self.precision();
+            let result = if exact_in {
+                let amount_in = self
+                    .token_ref(from_token_id)
+                    .normalize(amount_in, precision);
+                self.calc_concentrated_swap(from_token_id, amount_in, true)
+            } else {
+                let amount_out = self.token_ref(to_token_id).normalize(amount_out, precision);
+                self.calc_concentrated_swap(from_token_id, amount_out, false)
+            };
+            (result.price(), concentrated::Q64)
+        } else {
+            let (src_pool_balance, dst_pool_balance) =
+                self.pool_balances(from_token_id, to_token_id);
+            let protocol_fee_amount =
+                token::calc_protocol_fee(amount_in, self.fee, self.protocol_fee);
+            let src_pool_balance = src_pool_balance.0 + amount_in.0 - protocol_fee_amount.0;
+            let dst_pool_balance = dst_pool_balance.0 - amount_out.0;
+            if *from_token_id == self.token_a.id {
+                self.balances_price_ratio(src_pool_balance.into(), dst_pool_balance.into())
+            } else {
+                self.balances_price_ratio(dst_pool_balance.into(), src_pool_balance.into())
+            }
+        };
+
+        // Prices are of a token A, they are inverted for a source token B
+        let ((numerator, denominator), (numerator_after, denominator_after)) =
+            if *from_token_id == self.token_a.id {
+                (
+                    (numerator, denominator),
+                    (numerator_after, denominator_after),
+                )
+            } else {
+                (
+                    (denominator, numerator),
+                    (denominator_after, numerator_after),
+                )
+            };
+
+        let price_before = token::U256::from(numerator) * token::U256::from(denominator_after);
+        let price_after = token::U256::from(numerator_after) * token::U256::from(denominator);
+        let price_impact = if price_before.is_zero() {
+            0
+        } else {
+            let delta = if price_before > price_after {
+                price_before - price_after
+            } else {
+                price_after - price_before
+            };
+            (delta * token::U256::from(token::FEE_DIVISOR) / price_before)
+                .min(token::U256::from(u32::MAX))
+                .as_u32()
+        };
+
+        Quote {
+            amount_in,
+            amount_out,
+            fee: token::mul_div(amount_in.0, self.fee.into(), token::FEE_DIVISOR.into()).into(),
+            spot_price_before: token::format_ratio(numerator, denominator),
+            spot_price_after: token::format_ratio(numerator_after, denominator_after),
+            price_impact,
+        }
+    }
+
+    /// Calculates a swap of concentrated liquidity from a `from_token_id` for a normalized
+    /// `amount`, that is an input if `exact_in` or an output otherwise
+    fn calc_concentrated_swap(
+        &self,
+        from_token_id: &AccountId,
+        amount: u128,
+        exact_in: bool,
+    ) -> concentrated::SwapResult {
+        let a_to_b = *from_token_id == self.token_a.id;
+        self.concentrated_pool()
+            .calc_swap(a_to_b, amount, exact_in, self.fee, self.protocol_fee)
+    }
+
+    /// Moves the price of concentrated liquidity by a swap of an `amount` of a `token_id`,
+    /// that is an input of a `from_token_id` if `exact_in` or an output of a `to_token_id`.
+    /// Does nothing for other curves.
+    fn apply_concentrated_swap(&mut self, token_id: &AccountId, amount: U128, exact_in: bool) {
+        if self.concentrated.is_none() {
+            return;
+        }
+
+        self.update_oracle();
+        let precision = self.precision();
+        let amount = self.token(token_id).normalize(amount, precision);
+        let a_to_b = (*token_id == self.token_a.id) == exact_in;
+        let (fee, protocol_fee) = (self.fee, self.protocol_fee);
+        let pool = self.concentrated_pool_mut();
+        let result = pool.calc_swap(a_to_b, amount, exact_in, fee, protocol_fee);
+        pool.apply_swap(a_to_b, result);
+    }
+
+    fn concentrated_pool(&self) -> &ConcentratedPool {
+        self.concentrated
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("Pool doesn't have concentrated liquidity"))
+    }
+
+    fn concentrated_pool_mut(&mut self) -> &mut ConcentratedPool {
+        self.concentrated
+            .as_mut()
+            .unwrap_or_else(|| env::panic_str("Pool doesn't have concentrated liquidity"))
+    }
+
+    /// Transfers amounts of tokens A and B, zero amounts are skipped
+    fn transfer_pair(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount_a: U128,
+        amount_b: U128,
+    ) {
+        if amount_a.0 > 0 {
+            self.token_a.transfer(sender_id, receiver_id, amount_a);
+        }
+        if amount_b.0 > 0 {
+            self.token_b.transfer(sender_id, receiver_id, amount_b);
+        }
+    }
+
+    /// Pool curve oriented from a `from_token_id` to another token
+    fn swap_curve(&self, from_token_id: &AccountId) -> Curve {
+        if *from_token_id == self.token_a.id {
+            self.curve.clone()
+        } else {
+            self.curve.reversed()
+        }
+    }
+
+    /// Price of one token A in tokens B as a ratio
+    fn price_ratio(&self) -> (u128, u128) {
+        if let Some(pool) = &self.concentrated {
+            return (pool.price(), concentrated::Q64);
+        }
+
+        let contract_id = env::current_account_id();
+        self.balances_price_ratio(
+            self.token_a.balance_of(contract_id.clone()),
+            self.token_b.balance_of(contract_id),
+        )
+    }
+
+    /// Price of one token A in tokens B as a ratio of normalized and weighted pool balances
+    fn balances_price_ratio(&self, liq_balance_a: U128, liq_balance_b: U128) -> (u128, u128) {
+        let precision = self.precision();
+        let liq_balance_a = self.token_a.normalize(liq_balance_a, precision);
+        let liq_balance_b = self.token_b.normalize(liq_balance_b, precision);
+
+        let (weight_a, weight_b) = self.curve.weights();
+        (
+            liq_balance_b * u128::from(weight_a),
+            liq_balance_a * u128::from(weight_b),
+        )
+    }
+
+    /// Prices of a token A in tokens B and of a token B in tokens A in Q64.64
+    fn spot_prices(&self) -> (u128, u128) {
+        let (numerator, denominator) = self.price_ratio();
+        (
+            oracle::q64_price(numerator, denominator),
+            oracle::q64_price(denominator, numerator),
+        )
+    }
+
+    /// Accumulates prices in the oracle, it's called before any change of the pool
+    fn update_oracle(&mut self) {
+        let (price_a, price_b) = self.spot_prices();
+        self.oracle.update(price_a, price_b);
+    }
+
+    fn has_metadata(&self) -> bool {
+        self.token_a.decimals().is_some() && self.token_b.decimals().is_some()
+    }
+
+    /// The greatest decimals of pool tokens, all calculations are made in this precision
+    fn precision(&self) -> u8 {
+        let decimals_a = self.token_a.decimals();
+        let decimals_b = self.token_b.decimals();
+        match (decimals_a, decimals_b) {
+            (Some(decimals_a), Some(decimals_b)) => decimals_a.max(decimals_b),
+            _ => env::panic_str("Tokens metadata isn't fetched yet"),
+        }
+    }
+
+    /// Amounts of a top-up that are deposited to the pool, see [AmmContract::add_liquidity]
+    fn top_up_amounts(
+        &self,
+        liq_balance_a: U128,
+        liq_balance_b: U128,
+        amount_a: U128,
+        amount_b: U128,
+        optimal: bool,
+    ) -> (U128, U128) {
+        // Compare amounts in the same decimals
+        let precision = self.precision();
+        let liq_balance_a = self.token_a.normalize(liq_balance_a, precision);
+        let liq_balance_b = self.token_b.normalize(liq_balance_b, precision);
+        let amount_check_a = self.token_a.normalize(amount_a, precision);
+        let amount_check_b = self.token_b.normalize(amount_b, precision);
+
+        if optimal {
+            let (amount_a, amount_b) = token::optimal_amounts(
+                liq_balance_a,
+                liq_balance_b,
+                amount_check_a,
+                amount_check_b,
+            );
+            return (
+                self.token_a.denormalize(amount_a, precision),
+                self.token_b.denormalize(amount_b, precision),
+            );
+        }
+
+        if !token::is_proportional(
+            liq_balance_a,
+            liq_balance_b,
+            amount_check_a,
+            amount_check_b,
+            self.liquidity_tolerance,
+        ) {
+            env::panic_str("Incorrect amounts for top up a liquidity")
+        }
+        (amount_a, amount_b)
+    }
+
+    /// Returns pool balances of source and destination tokens, both of them should be positive
+    fn pool_balances(&self, from_token_id: &AccountId, to_token_id: &AccountId) -> (U128, U128) {
+        let contract_id = env::current_account_id();
+
+        // Get current statement of pool
+        let src_pool_balance = self
+            .token_ref(from_token_id)
+            .balance_of(contract_id.clone());
+        let dst_pool_balance = self.token_ref(to_token_id).balance_of(contract_id);
+
+        if src_pool_balance == 0.into() || dst_pool_balance == 0.into() {
+            env::panic_str("Pool balance couldn't be equal to 0");
+        }
+
+        (src_pool_balance, dst_pool_balance)
+    }
+
+    /// Moves `amount_in` from a user to the pool and `amount_out` from the pool to a user
+    fn internal_swap(
+        &mut self,
+        user_account_id: &AccountId,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount_in: U128,
+        amount_out: U128,
+    ) {
+        let contract_id = env::current_account_id();
+
+        if self
+            .token(from_token_id)
+            .balance_of(user_account_id.clone())
+            < amount_in
+        {
+            env::panic_str(
+                format!("The user {} doesn't have enough funds", user_account_id).as_str(),
+            );
+        }
+
+        // The protocol part of the fee doesn't go to the pool
+        self.update_oracle();
+        let protocol_fee_amount = token::calc_protocol_fee(amount_in, self.fee, self.protocol_fee);
+        self.token(from_token_id)
+            .accrue_protocol_fee(user_account_id, protocol_fee_amount);
+        self.token(from_token_id).transfer(
+            user_account_id,
+            &contract_id,
+            (amount_in.0 - protocol_fee_amount.0).into(),
+        );
+
+        self.token(to_token_id)
+            .transfer(&contract_id, user_account_id, amount_out);
+        AmmEvent::Swap {
+            account_id: user_account_id,
+            token_in: from_token_id,
+            amount_in,
+            token_out: to_token_id,
+            amount_out,
+            protocol_fee: protocol_fee_amount,
+        }
+        .emit();
+    }
+
+    /// Moves transferred `amount_in` to the pool and `amount_out` out of the pool
+    /// to send it to the next pool of a route, returns the protocol part of the fee
+    fn internal_route_swap(
+        &mut self,
+        from_token_id: &AccountId,
+        to_token_id: &AccountId,
+        amount_in: U128,
+        amount_out: U128,
+    ) -> U128 {
+        let contract_id = env::current_account_id();
+        self.update_oracle();
+
+        let protocol_fee_amount = token::calc_protocol_fee(amount_in, self.fee, self.protocol_fee);
+        let from_token = self.token(from_token_id);
+        from_token.deposit(&contract_id, (amount_in.0 - protocol_fee_amount.0).into());
+        from_token.protocol_fees += protocol_fee_amount.0;
+
+        self.token(to_token_id).withdraw(&contract_id, amount_out);
+        protocol_fee_amount
+    }
+
     fn token(&mut self, token_id: &AccountId) -> &mut Token {
         match token_id {
             id if *id == self.token_a.id => &mut self.token_a,
@@ -229,6 +1649,14 @@ impl AmmContract {
         }
     }
 
+    fn token_ref(&self, token_id: &AccountId) -> &Token {
+        match token_id {
+            id if *id == self.token_a.id => &self.token_a,
+            id if *id == self.token_b.id => &self.token_b,
+            _ => env::panic_str(format!("Doesn't support passed token_id {token_id}").as_str()),
+        }
+    }
+
     #[private]
     pub fn set_token_metadata(&mut self, token_type: TokenType) {
         assert_eq!(env::promise_results_count(), 1, "Expected 1 promise result");
@@ -256,13 +1684,92 @@ impl AmmContract {
         amount: U128,
     ) {
         assert_eq!(env::promise_results_count(), 1, "Expected 1 promise result");
+        self.finish_pending_transfer(&user_account_id);
         match env::promise_result(0) {
             PromiseResult::NotReady => env::panic_str("Token withdraw callback not ready"),
-            PromiseResult::Successful(_) => {
-                self.token(&token_id).withdraw(&user_account_id, amount);
+            PromiseResult::Successful(_) => AmmEvent::WithdrawCompleted {
+                account_id: &user_account_id,
+                token_id: &token_id,
+                amount,
+            }
+            .emit(),
+            PromiseResult::Failed => {
+                // The amount is debited before the transfer, so it's credited back,
+                // the account couldn't be unregistered while the transfer is pending
+                self.token(&token_id).deposit(&user_account_id, amount);
+                AmmEvent::WithdrawFailed {
+                    account_id: &user_account_id,
+                    token_id: &token_id,
+                    amount,
+                }
+                .emit();
+            }
+        }
+    }
+
+    /// Resolves a hop of a route, the hop is rolled back if the next pool refunded the output.
+    /// Returns an unused amount of the hop input, so the previous pool rolls back as well.
+    #[private]
+    pub fn on_route_hop(
+        &mut self,
+        receiver_id: AccountId,
+        from_token_id: AccountId,
+        to_token_id: AccountId,
+        amount_in: U128,
+        amount_out: U128,
+        protocol_fee_amount: U128,
+    ) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "Expected 1 promise result");
+        self.finish_pending_transfer(&receiver_id);
+        let used_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => env::panic_str("Route hop promise isn't ready"),
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).unwrap_or_else(|_| 0.into())
+            }
+            PromiseResult::Failed => 0.into(),
+        };
+
+        let contract_id = env::current_account_id();
+        if used_amount == 0.into() {
+            // The output is returned, so the pool takes it back and refunds the input.
+            // Protocol fees could be claimed and the reserve could be removed in the meantime,
+            // only the rest is refunded then, so the rollback never fails.
+            self.update_oracle();
+            self.token(&to_token_id).deposit(&contract_id, amount_out);
+            let from_token = self.token(&from_token_id);
+            let reserve = from_token.balance_of(contract_id.clone()).0;
+            let reserve_refund = (amount_in.0 - protocol_fee_amount.0).min(reserve);
+            let fee_refund = protocol_fee_amount.0.min(from_token.protocol_fees);
+            from_token.withdraw(&contract_id, reserve_refund.into());
+            from_token.protocol_fees -= fee_refund;
+
+            let refund = U128::from(reserve_refund + fee_refund);
+            if refund < amount_in {
+                log!(
+                    "Only {:?} of {:?} of {} could be refunded",
+                    refund,
+                    amount_in,
+                    from_token_id
+                );
+            }
+
+            AmmEvent::SwapRollback {
+                token_in: &from_token_id,
+                amount_in: refund,
+                token_out: &to_token_id,
+                amount_out,
             }
-            PromiseResult::Failed => env::panic_str("Token withdraw failed"),
+            .emit();
+            return refund;
         }
+
+        // A partially refunded output can't be swapped back, it's kept for the receiver,
+        // that couldn't be unregistered while the hop is pending
+        if used_amount < amount_out {
+            let refund = U128::from(amount_out.0 - used_amount.0);
+            self.token(&to_token_id).deposit(&receiver_id, refund);
+        }
+        0.into()
     }
 }
 
@@ -272,19 +1779,95 @@ impl FungibleTokenReceiver for AmmContract {
         &mut self,
         sender_id: AccountId,
         amount: U128,
-        #[allow(unused_variables)] msg: String,
+        msg: String,
     ) -> PromiseOrValue<U128> {
         let token_id = env::predecessor_account_id();
-        if self.token_a.id == token_id {
-            self.token_a.deposit(&sender_id, amount);
-            PromiseOrValue::Value(0.into())
-        } else if self.token_b.id == token_id {
-            self.token_b.deposit(&sender_id, amount);
-            PromiseOrValue::Value(0.into())
-        } else {
+        let result = self.internal_ft_on_transfer(&sender_id, &token_id, amount, msg);
+        if let PromiseOrValue::Value(refund) = result {
+            if refund.0 > 0 {
+                AmmEvent::Refund {
+                    account_id: &sender_id,
+                    token_id: &token_id,
+                    amount: refund,
+                }
+                .emit();
+            }
+        }
+        result
+    }
+}
+
+impl AmmContract {
+    /// Handles a transfer of `ft_on_transfer`, returns an amount that is refunded
+    /// or a promise of the output transfer
+    fn internal_ft_on_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        token_id: &AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        if &self.token_a.id != token_id && &self.token_b.id != token_id {
             log!("Doesn't support such token");
-            PromiseOrValue::Value(amount)
+            return PromiseOrValue::Value(amount);
+        }
+        if self.pause.is_paused(Operation::Deposit) {
+            log!("Deposits are paused");
+            return PromiseOrValue::Value(amount);
+        }
+
+        if msg.is_empty() {
+            if !self.is_registered(sender_id) {
+                log!("The account {} is not registered", sender_id);
+                return PromiseOrValue::Value(amount);
+            }
+            self.token(token_id).deposit(sender_id, amount);
+            AmmEvent::Deposit {
+                account_id: sender_id,
+                token_id,
+                amount,
+            }
+            .emit();
+            return PromiseOrValue::Value(0.into());
+        }
+
+        if self.pause.is_paused(Operation::Swap) {
+            log!("Swaps are paused");
+            return PromiseOrValue::Value(amount);
         }
+        match near_sdk::serde_json::from_str::<TransferMessage>(&msg) {
+            Ok(TransferMessage::Swap { to, min_out }) => {
+                self.swap_on_transfer(sender_id.clone(), token_id.clone(), to, amount, min_out)
+            }
+            Ok(TransferMessage::Route {
+                path,
+                min_out,
+                receiver_id,
+            }) => {
+                let receiver_id = receiver_id.unwrap_or_else(|| sender_id.clone());
+                self.route_on_transfer(receiver_id, token_id.clone(), path, amount, min_out)
+            }
+            Err(err) => {
+                log!("Couldn't parse a message {}: {}", msg, err);
+                PromiseOrValue::Value(amount)
+            }
+        }
+    }
+}
+
+fn assert_valid_fee(fee: u32) {
+    if fee >= token::FEE_DIVISOR {
+        env::panic_str(format!("Fee {fee} should be less than {}", token::FEE_DIVISOR).as_str());
+    }
+}
+
+fn token_info(token: &Token) -> TokenInfo {
+    let metadata = token.metadata.as_ref();
+    TokenInfo {
+        id: token.id.clone(),
+        symbol: metadata.map(|metadata| metadata.symbol.clone()),
+        decimals: metadata.map(|metadata| metadata.decimals),
+        reserve: token.balance_of(env::current_account_id()),
     }
 }
 
@@ -293,3 +1876,298 @@ fn metadata(token_id: AccountId, token_type: TokenType) -> Promise {
         .ft_metadata()
         .then(ext_self::ext(env::current_account_id()).set_token_metadata(token_type))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::{
+        test_utils::VMContextBuilder, testing_env, Balance, RuntimeFeesConfig, VMConfig,
+    };
+
+    fn set_context(predecessor: &str, deposit: Balance) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("pool.near".parse().unwrap())
+            .predecessor_account_id(predecessor.parse().unwrap())
+            .attached_deposit(deposit)
+            .build());
+    }
+
+    /// Context of a callback that receives a `result` of the previous promise
+    fn set_callback_context(result: PromiseResult) {
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id("pool.near".parse().unwrap())
+                .predecessor_account_id("pool.near".parse().unwrap())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
+    fn pool() -> AmmContract {
+        set_context("owner.near", 0);
+        let mut pool = AmmContract::initialize(
+            "owner.near".parse().unwrap(),
+            "token-a.near".parse().unwrap(),
+            "token-b.near".parse().unwrap(),
+            30,
+            None,
+        );
+        let min = pool.storage_balance_bounds().min;
+        set_context("alice.near", min.0);
+        pool.storage_deposit(None, None);
+        pool
+    }
+
+    /// Pool with fetched metadata of tokens and a liquidity of `reserve` of each token
+    fn pool_with_liquidity(reserve: u128) -> AmmContract {
+        let mut pool = pool();
+        let contract_id: AccountId = "pool.near".parse().unwrap();
+        for token in [&mut pool.token_a, &mut pool.token_b] {
+            token.metadata = Some(FungibleTokenMetadata {
+                spec: FT_METADATA_SPEC.to_string(),
+                name: token.id.to_string(),
+                symbol: token.id.to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals: 10,
+            });
+            token.try_register_account(&contract_id);
+            token.deposit(&contract_id, reserve.into());
+        }
+        pool
+    }
+
+    fn alice() -> AccountId {
+        "alice.near".parse().unwrap()
+    }
+
+    fn token_a() -> AccountId {
+        "token-a.near".parse().unwrap()
+    }
+
+    fn token_b() -> AccountId {
+        "token-b.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_withdraw_is_debited_before_transfer() {
+        let mut pool = pool();
+        pool.token_a.deposit(&alice(), 100.into());
+
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), 60.into());
+        assert_eq!(U128::from(40), pool.balance_of_token_a(alice()));
+
+        set_callback_context(PromiseResult::Successful(vec![]));
+        pool.withdraw_token_callback(token_a(), alice(), 60.into());
+        assert_eq!(U128::from(40), pool.balance_of_token_a(alice()));
+    }
+
+    #[test]
+    fn test_failed_withdraw_is_credited_back() {
+        let mut pool = pool();
+        pool.token_a.deposit(&alice(), 100.into());
+
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), 60.into());
+
+        set_callback_context(PromiseResult::Failed);
+        pool.withdraw_token_callback(token_a(), alice(), 60.into());
+        assert_eq!(U128::from(100), pool.balance_of_token_a(alice()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with pending transfers")]
+    fn test_unregister_with_pending_withdrawal() {
+        let mut pool = pool();
+        pool.token_a.deposit(&alice(), 100.into());
+
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), 100.into());
+
+        set_context("alice.near", 1);
+        pool.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn test_failed_withdraw_of_whole_balance_is_credited_back() {
+        let mut pool = pool();
+        pool.token_a.deposit(&alice(), 100.into());
+
+        // The zero balance doesn't allow to unregister while the withdrawal is pending
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), 100.into());
+        assert_eq!(U128::from(0), pool.balance_of_token_a(alice()));
+        assert!(pool.pending_transfers.contains_key(&alice()));
+
+        set_callback_context(PromiseResult::Failed);
+        pool.withdraw_token_callback(token_a(), alice(), 100.into());
+        assert_eq!(U128::from(100), pool.balance_of_token_a(alice()));
+        assert!(!pool.pending_transfers.contains_key(&alice()));
+
+        // The user withdraws the funds again and then unregisters
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), 100.into());
+        set_callback_context(PromiseResult::Successful(vec![]));
+        pool.withdraw_token_callback(token_a(), alice(), 100.into());
+        set_context("alice.near", 1);
+        assert!(pool.storage_unregister(None));
+    }
+
+    #[test]
+    fn test_route_rollback_after_protocol_fees_are_claimed() {
+        let mut pool = pool_with_liquidity(1_000_000);
+        pool.protocol_fee = 2_000;
+        let protocol_fee_amount =
+            pool.internal_route_swap(&token_a(), &token_b(), 100_000.into(), 90_000.into());
+        assert_eq!(U128::from(60), protocol_fee_amount);
+
+        // Anyone claims fees before the next pool refunds the output
+        set_context("owner.near", 0);
+        pool.set_treasury(alice());
+        pool.claim_protocol_fees();
+
+        set_callback_context(PromiseResult::Failed);
+        let refund = pool.on_route_hop(
+            alice(),
+            token_a(),
+            token_b(),
+            100_000.into(),
+            90_000.into(),
+            protocol_fee_amount,
+        );
+        // The claimed fee isn't refunded, reserves are restored
+        assert_eq!(U128::from(99_940), refund);
+        assert_eq!(U128::from(1_000_000), pool.token_a_in_pool());
+        assert_eq!(U128::from(1_000_000), pool.token_b_in_pool());
+        assert_eq!(U128::from(0), pool.token_a_protocol_fees());
+    }
+
+    fn route_path() -> Vec<RouteHop> {
+        vec![
+            RouteHop {
+                pool_id: "pool.near".parse().unwrap(),
+                to: token_b(),
+            },
+            RouteHop {
+                pool_id: "pool-bc.near".parse().unwrap(),
+                to: "token-c.near".parse().unwrap(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_route_to_unregistered_receiver_is_refunded() {
+        let mut pool = pool_with_liquidity(1_000_000);
+        set_context("token-a.near", 0);
+        let result = pool.route_on_transfer(
+            "bob.near".parse().unwrap(),
+            token_a(),
+            route_path(),
+            100_000.into(),
+            None,
+        );
+        assert!(matches!(result, PromiseOrValue::Value(amount) if amount == 100_000.into()));
+        assert_eq!(U128::from(1_000_000), pool.token_a_in_pool());
+    }
+
+    #[test]
+    fn test_partial_refund_of_route_hop_is_kept_for_receiver() {
+        let mut pool = pool_with_liquidity(1_000_000);
+        set_context("token-a.near", 0);
+        let result = pool.route_on_transfer(alice(), token_a(), route_path(), 100_000.into(), None);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(pool.pending_transfers.contains_key(&alice()));
+        let amount_out = 1_000_000 - pool.token_b_in_pool().0;
+
+        // The next pool used only 10_000 of the output
+        set_callback_context(PromiseResult::Successful(b"\"10000\"".to_vec()));
+        let refund = pool.on_route_hop(
+            alice(),
+            token_a(),
+            token_b(),
+            100_000.into(),
+            amount_out.into(),
+            0.into(),
+        );
+        assert_eq!(U128::from(0), refund);
+        assert_eq!(
+            U128::from(amount_out - 10_000),
+            pool.balance_of_token_b(alice())
+        );
+        assert!(!pool.pending_transfers.contains_key(&alice()));
+    }
+
+    #[test]
+    fn test_treasury_withdraws_claimed_fees() {
+        let mut pool = pool_with_liquidity(1_000_000);
+        set_context("owner.near", 0);
+        pool.set_treasury(alice());
+        pool.protocol_fee = 2_000;
+        pool.internal_route_swap(&token_a(), &token_b(), 100_000.into(), 90_000.into());
+
+        let (amount_a, _) = pool.claim_protocol_fees();
+        assert_eq!(U128::from(60), amount_a);
+        assert_eq!(U128::from(60), pool.balance_of_token_a(alice()));
+
+        // A failed withdrawal returns fees to the treasury balance, not to protocol fees
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), amount_a);
+        set_callback_context(PromiseResult::Failed);
+        pool.withdraw_token_callback(token_a(), alice(), amount_a);
+        assert_eq!(U128::from(60), pool.balance_of_token_a(alice()));
+        assert_eq!(U128::from(0), pool.token_a_protocol_fees());
+
+        set_context("alice.near", 0);
+        pool.withdraw_token(token_a(), amount_a);
+        set_callback_context(PromiseResult::Successful(vec![]));
+        pool.withdraw_token_callback(token_a(), alice(), amount_a);
+        assert_eq!(U128::from(0), pool.balance_of_token_a(alice()));
+    }
+
+    #[test]
+    fn test_pool_info_before_metadata_is_fetched() {
+        let pool = pool();
+        let pool_info = pool.pool_info();
+        assert!(pool_info.exchange_rate.is_none());
+        assert!(pool_info.token_a.decimals.is_none());
+
+        let pool = pool_with_liquidity(1_000_000);
+        assert_eq!(Some("1".to_string()), pool.pool_info().exchange_rate);
+    }
+
+    #[test]
+    fn test_share_transfer_emits_single_event() {
+        let mut pool = pool();
+        let min = pool.storage_balance_bounds().min;
+        set_context("owner.near", min.0);
+        pool.storage_deposit(None, None);
+        pool.shares.deposit(&alice(), 10.into());
+
+        set_context("alice.near", 1);
+        pool.ft_transfer("owner.near".parse().unwrap(), 4.into(), None);
+        let events = near_sdk::test_utils::get_logs()
+            .into_iter()
+            .filter(|log| log.contains(r#""event":"ft_transfer""#))
+            .count();
+        assert_eq!(1, events);
+    }
+
+    #[test]
+    fn test_operator_isnt_admin() {
+        let mut pool = pool();
+        set_context("owner.near", 0);
+        pool.approve_operator(alice());
+        assert!(pool.has_role(Role::FeeManager, alice()));
+        assert!(!pool.has_role(Role::Admin, alice()));
+
+        pool.revoke_operator(alice());
+        assert!(!pool.has_role(Role::FeeManager, alice()));
+    }
+}
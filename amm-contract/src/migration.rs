@@ -0,0 +1,413 @@
+//! Versioned layouts of the contract state.
+//!
+//! The state is stored as a plain [AmmContract] and its layout version is stored separately
+//! under [VERSION_KEY], a missing version is the baseline layout. When the layout of [AmmContract]
+//! or of anything it holds is changed, the previous layout is kept here as an old struct with
+//! a new variant of [VersionedState] and a conversion to the current layout.
+
+use near_contract_standards::fungible_token::{
+    events::FtMint, metadata::FungibleTokenMetadata, FungibleToken,
+};
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, UnorderedSet},
+    env, AccountId,
+};
+
+use crate::concentrated::ConcentratedPool;
+use crate::curve::Curve;
+use crate::oracle::Oracle;
+use crate::pause::PauseState;
+use crate::roles::Roles;
+use crate::token::{self, Token};
+use crate::{AmmContract, DEFAULT_LIQUIDITY_TOLERANCE};
+
+/// Version of the layout that is written by this code
+pub const STATE_VERSION: u8 = 5;
+const VERSION_KEY: &[u8] = b"VERSION";
+
+/// Layouts of the state that could be read by [AmmContract::migrate]
+pub enum VersionedState {
+    /// Baseline layout with the owner and tokens A and B
+    V0(AmmContractV0),
+    /// Layout with oracle, storage management, ownership transfer and pause
+    V1(AmmContractV1),
+    /// Layout with roles
+    V2(AmmContractV2),
+    /// Layout with operators of the owner
+    V3(AmmContractV3),
+    /// Layout with the liquidity tolerance
+    V4(AmmContractV4),
+    /// Layout with pending transfers of accounts
+    V5(AmmContract),
+}
+
+/// Baseline [AmmContract], it's stored without a version
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AmmContractV0 {
+    owner_id: AccountId,
+    token_a: TokenV0,
+    token_b: TokenV0,
+}
+
+/// [Token] before protocol fees were added
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokenV0 {
+    id: AccountId,
+    internal_token: FungibleToken,
+    metadata: Option<FungibleTokenMetadata>,
+}
+
+/// [AmmContract] before roles were added
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AmmContractV1 {
+    owner_id: AccountId,
+    proposed_owner_id: Option<AccountId>,
+    token_a: Token,
+    token_b: Token,
+    shares: Token,
+    fee: u32,
+    protocol_fee: u32,
+    treasury_id: AccountId,
+    curve: Curve,
+    concentrated: Option<ConcentratedPool>,
+    oracle: Oracle,
+    pause: PauseState,
+}
+
+impl VersionedState {
+    /// Reads the state in a layout of the stored version
+    pub fn read() -> Self {
+        let version = state_version();
+        match version {
+            0 => VersionedState::V0(read_state()),
+            1 => VersionedState::V1(read_state()),
+            2 => VersionedState::V2(read_state()),
+            3 => VersionedState::V3(read_state()),
+            4 => VersionedState::V4(read_state()),
+            5 => VersionedState::V5(read_state()),
+            _ => env::panic_str(format!("Unknown version {version} of the state").as_str()),
+        }
+    }
+}
+
+/// [AmmContract] before operators were added
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AmmContractV2 {
+    owner_id: AccountId,
+    proposed_owner_id: Option<AccountId>,
+    token_a: Token,
+    token_b: Token,
+    shares: Token,
+    fee: u32,
+    protocol_fee: u32,
+    treasury_id: AccountId,
+    curve: Curve,
+    concentrated: Option<ConcentratedPool>,
+    oracle: Oracle,
+    pause: PauseState,
+    roles: Roles,
+}
+
+/// [AmmContract] before the liquidity tolerance was added
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AmmContractV3 {
+    owner_id: AccountId,
+    proposed_owner_id: Option<AccountId>,
+    token_a: Token,
+    token_b: Token,
+    shares: Token,
+    fee: u32,
+    protocol_fee: u32,
+    treasury_id: AccountId,
+    curve: Curve,
+    concentrated: Option<ConcentratedPool>,
+    oracle: Oracle,
+    pause: PauseState,
+    roles: Roles,
+    operators: UnorderedSet<AccountId>,
+}
+
+/// [AmmContract] before pending transfers were counted
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AmmContractV4 {
+    owner_id: AccountId,
+    proposed_owner_id: Option<AccountId>,
+    token_a: Token,
+    token_b: Token,
+    shares: Token,
+    fee: u32,
+    protocol_fee: u32,
+    treasury_id: AccountId,
+    curve: Curve,
+    concentrated: Option<ConcentratedPool>,
+    oracle: Oracle,
+    pause: PauseState,
+    roles: Roles,
+    operators: UnorderedSet<AccountId>,
+    liquidity_tolerance: u32,
+}
+
+impl From<VersionedState> for AmmContract {
+    /// Each layout is converted to the next one until it's the current one
+    fn from(state: VersionedState) -> Self {
+        match state {
+            VersionedState::V0(contract) => VersionedState::V1(contract.into()).into(),
+            VersionedState::V1(contract) => VersionedState::V2(contract.into()).into(),
+            VersionedState::V2(contract) => VersionedState::V3(contract.into()).into(),
+            VersionedState::V3(contract) => VersionedState::V4(contract.into()).into(),
+            VersionedState::V4(contract) => VersionedState::V5(contract.into()).into(),
+            VersionedState::V5(contract) => contract,
+        }
+    }
+}
+
+impl From<TokenV0> for Token {
+    fn from(token: TokenV0) -> Self {
+        Token {
+            id: token.id,
+            internal_token: token.internal_token,
+            metadata: token.metadata,
+            protocol_fees: 0,
+        }
+    }
+}
+
+impl From<AmmContractV0> for AmmContractV1 {
+    /// The baseline pool charged no fee and only the owner provided its liquidity,
+    /// so the owner receives LP shares as for the first deposit of the liquidity
+    fn from(contract: AmmContractV0) -> Self {
+        let contract_id = env::current_account_id();
+        let token_a = Token::from(contract.token_a);
+        let token_b = Token::from(contract.token_b);
+        let mut shares = Token::new(contract_id.clone(), b"s".to_vec());
+        let liquidity = token::calc_shares(
+            0.into(),
+            0.into(),
+            token_a.balance_of(contract_id.clone()),
+            token_b.balance_of(contract_id),
+            0.into(),
+        );
+        if liquidity.0 > 0 {
+            shares.try_register_account(&contract.owner_id);
+            shares.deposit(&contract.owner_id, liquidity);
+            FtMint {
+                owner_id: &contract.owner_id,
+                amount: &liquidity,
+                memo: Some("migrate"),
+            }
+            .emit();
+        }
+
+        AmmContractV1 {
+            treasury_id: contract.owner_id.clone(),
+            owner_id: contract.owner_id,
+            proposed_owner_id: None,
+            token_a,
+            token_b,
+            shares,
+            fee: 0,
+            protocol_fee: 0,
+            curve: Curve::default(),
+            concentrated: None,
+            oracle: Oracle::new(),
+            pause: PauseState::default(),
+        }
+    }
+}
+
+impl From<AmmContractV1> for AmmContractV2 {
+    /// Nobody is granted roles yet, the owner holds all of them
+    fn from(contract: AmmContractV1) -> Self {
+        AmmContractV2 {
+            owner_id: contract.owner_id,
+            proposed_owner_id: contract.proposed_owner_id,
+            token_a: contract.token_a,
+            token_b: contract.token_b,
+            shares: contract.shares,
+            fee: contract.fee,
+            protocol_fee: contract.protocol_fee,
+            treasury_id: contract.treasury_id,
+            curve: contract.curve,
+            concentrated: contract.concentrated,
+            oracle: contract.oracle,
+            pause: contract.pause,
+            roles: Roles::new(b"r".to_vec()),
+        }
+    }
+}
+
+impl From<AmmContractV2> for AmmContractV3 {
+    /// The owner doesn't have operators yet
+    fn from(contract: AmmContractV2) -> Self {
+        AmmContractV3 {
+            owner_id: contract.owner_id,
+            proposed_owner_id: contract.proposed_owner_id,
+            token_a: contract.token_a,
+            token_b: contract.token_b,
+            shares: contract.shares,
+            fee: contract.fee,
+            protocol_fee: contract.protocol_fee,
+            treasury_id: contract.treasury_id,
+            curve: contract.curve,
+            concentrated: contract.concentrated,
+            oracle: contract.oracle,
+            pause: contract.pause,
+            roles: contract.roles,
+            operators: UnorderedSet::new(b"d".to_vec()),
+        }
+    }
+}
+
+impl From<AmmContractV3> for AmmContractV4 {
+    /// Top-ups were checked with a truncated rate, the default tolerance is stricter
+    fn from(contract: AmmContractV3) -> Self {
+        AmmContractV4 {
+            owner_id: contract.owner_id,
+            proposed_owner_id: contract.proposed_owner_id,
+            token_a: contract.token_a,
+            token_b: contract.token_b,
+            shares: contract.shares,
+            fee: contract.fee,
+            protocol_fee: contract.protocol_fee,
+            treasury_id: contract.treasury_id,
+            curve: contract.curve,
+            concentrated: contract.concentrated,
+            oracle: contract.oracle,
+            pause: contract.pause,
+            roles: contract.roles,
+            operators: contract.operators,
+            liquidity_tolerance: DEFAULT_LIQUIDITY_TOLERANCE,
+        }
+    }
+}
+
+impl From<AmmContractV4> for AmmContract {
+    /// Withdrawals that were started before the upgrade aren't counted
+    fn from(contract: AmmContractV4) -> Self {
+        AmmContract {
+            owner_id: contract.owner_id,
+            proposed_owner_id: contract.proposed_owner_id,
+            token_a: contract.token_a,
+            token_b: contract.token_b,
+            shares: contract.shares,
+            fee: contract.fee,
+            protocol_fee: contract.protocol_fee,
+            treasury_id: contract.treasury_id,
+            curve: contract.curve,
+            concentrated: contract.concentrated,
+            oracle: contract.oracle,
+            pause: contract.pause,
+            roles: contract.roles,
+            operators: contract.operators,
+            liquidity_tolerance: contract.liquidity_tolerance,
+            pending_transfers: LookupMap::new(b"w".to_vec()),
+        }
+    }
+}
+
+fn read_state<T: BorshDeserialize>() -> T {
+    env::state_read().unwrap_or_else(|| env::panic_str("Contract state is missing"))
+}
+
+/// Marks the stored state with [STATE_VERSION]
+pub fn write_state_version() {
+    env::storage_write(VERSION_KEY, &[STATE_VERSION]);
+}
+
+/// Version of the stored state, a missing version is the baseline one
+pub fn state_version() -> u8 {
+    env::storage_read(VERSION_KEY)
+        .and_then(|version| version.first().copied())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roles::Role;
+    use near_sdk::{json_types::U128, test_utils::VMContextBuilder, testing_env};
+
+    fn set_context() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("pool.near".parse().unwrap())
+            .predecessor_account_id("pool.near".parse().unwrap())
+            .build());
+    }
+
+    fn token_v0(id: &str, prefix: &[u8], reserve: u128) -> TokenV0 {
+        let contract_id: AccountId = "pool.near".parse().unwrap();
+        let mut internal_token = FungibleToken::new(prefix.to_vec());
+        internal_token.internal_register_account(&contract_id);
+        internal_token.internal_deposit(&contract_id, reserve);
+        TokenV0 {
+            id: id.parse().unwrap(),
+            internal_token,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_migrate_unversioned_state() {
+        set_context();
+        // Baseline pools don't have a stored version
+        env::state_write(&AmmContractV0 {
+            owner_id: "owner.near".parse().unwrap(),
+            token_a: token_v0("token-a.near", b"a", 1_000),
+            token_b: token_v0("token-b.near", b"b", 4_000),
+        });
+        env::storage_remove(VERSION_KEY);
+        assert_eq!(0, state_version());
+
+        let pool = AmmContract::migrate();
+        assert_eq!(0, pool.fee());
+        assert_eq!("owner.near", pool.get_owner().as_str());
+        assert_eq!(U128::from(1_000), pool.token_a_in_pool());
+        assert_eq!(U128::from(4_000), pool.token_b_in_pool());
+        assert_eq!(0, pool.token_a.protocol_fees);
+        // The owner holds all shares of the baseline liquidity
+        assert_eq!(
+            U128::from(2_000),
+            pool.shares.balance_of("owner.near".parse().unwrap())
+        );
+        assert_eq!(U128::from(2_000), pool.shares.total_supply());
+        assert!(pool.get_role_members(Role::Admin).is_empty());
+        assert!(pool.get_operators().is_empty());
+        assert_eq!(DEFAULT_LIQUIDITY_TOLERANCE, pool.liquidity_tolerance());
+        assert_eq!(STATE_VERSION, state_version());
+    }
+
+    #[test]
+    fn test_migrate_first_version() {
+        set_context();
+        let pool = AmmContract::initialize(
+            "owner.near".parse().unwrap(),
+            "token-a.near".parse().unwrap(),
+            "token-b.near".parse().unwrap(),
+            30,
+            None,
+        );
+        env::state_write(&AmmContractV1 {
+            owner_id: pool.owner_id,
+            proposed_owner_id: pool.proposed_owner_id,
+            token_a: pool.token_a,
+            token_b: pool.token_b,
+            shares: pool.shares,
+            fee: pool.fee,
+            protocol_fee: pool.protocol_fee,
+            treasury_id: pool.treasury_id,
+            curve: pool.curve,
+            concentrated: pool.concentrated,
+            oracle: pool.oracle,
+            pause: pool.pause,
+        });
+        env::storage_write(VERSION_KEY, &[1]);
+
+        let pool = AmmContract::migrate();
+        assert_eq!(30, pool.fee());
+        assert_eq!("owner.near", pool.get_owner().as_str());
+        assert!(pool.get_role_members(Role::Admin).is_empty());
+        assert_eq!(DEFAULT_LIQUIDITY_TOLERANCE, pool.liquidity_tolerance());
+        assert_eq!(STATE_VERSION, state_version());
+    }
+}
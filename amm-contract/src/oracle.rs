@@ -0,0 +1,261 @@
+//! Uniswap v2-style time-weighted average price.
+//!
+//! Prices of both tokens are accumulated as a sum of `price * seconds` it was held, so an average
+//! price of a period is a difference of accumulators divided by its duration. Accumulators are
+//! updated before every change of the pool, and snapshots of them are kept in a ring buffer.
+
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::Vector,
+    env,
+};
+
+use crate::concentrated::Q64;
+use crate::token::U256;
+
+/// Maximum number of kept observations, the oldest one is overwritten by a new one
+pub const MAX_OBSERVATIONS: u64 = 256;
+/// Minimum number of seconds between observations, so the buffer covers at least
+/// `MAX_OBSERVATIONS * OBSERVATION_PERIOD` seconds, that is about 4 hours
+pub const OBSERVATION_PERIOD: u64 = 60;
+
+/// Snapshot of accumulators at a `timestamp` in seconds
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct Observation {
+    pub timestamp: u64,
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Oracle {
+    /// Sum of prices of a token A in tokens B in Q64.64 multiplied by seconds they were held.
+    /// It could overflow, only differences of accumulators are meaningful.
+    price_a_cumulative: u128,
+    /// The same sum of prices of a token B in tokens A
+    price_b_cumulative: u128,
+    /// Time of the last update in seconds
+    last_timestamp: u64,
+    observations: Vector<Observation>,
+    /// Index of the latest observation in the ring buffer
+    last_index: u64,
+}
+
+impl Oracle {
+    pub fn new() -> Self {
+        let mut observations = Vector::new(b"o".to_vec());
+        let timestamp = now();
+        observations.push(&Observation {
+            timestamp,
+            price_a_cumulative: 0,
+            price_b_cumulative: 0,
+        });
+
+        Self {
+            price_a_cumulative: 0,
+            price_b_cumulative: 0,
+            last_timestamp: timestamp,
+            observations,
+            last_index: 0,
+        }
+    }
+
+    /// Accumulates prices in Q64.64 that were held since the last update,
+    /// it should be called before any change of the pool
+    pub fn update(&mut self, price_a: u128, price_b: u128) {
+        let timestamp = now();
+        if timestamp <= self.last_timestamp {
+            return;
+        }
+
+        let current = self.current(price_a, price_b);
+        self.price_a_cumulative = current.price_a_cumulative;
+        self.price_b_cumulative = current.price_b_cumulative;
+        self.last_timestamp = timestamp;
+
+        let latest = self.observations.get(self.last_index).unwrap();
+        if timestamp < latest.timestamp + OBSERVATION_PERIOD {
+            return;
+        }
+        if self.observations.len() < MAX_OBSERVATIONS {
+            self.observations.push(&current);
+            self.last_index = self.observations.len() - 1;
+        } else {
+            self.last_index = (self.last_index + 1) % MAX_OBSERVATIONS;
+            self.observations.replace(self.last_index, &current);
+        }
+    }
+
+    /// Calculates an average price in Q64.64 of a token A if `token_a`, or of a token B
+    /// otherwise, for the last `window` seconds. Current prices are `price_a` and `price_b`.
+    pub fn consult(&self, price_a: u128, price_b: u128, token_a: bool, window: u64) -> u128 {
+        let current = self.current(price_a, price_b);
+        if window == 0 || window > current.timestamp {
+            env::panic_str(format!("Incorrect window of {window} seconds").as_str());
+        }
+
+        let start = self.cumulative_at(current, current.timestamp - window);
+        let (start, end) = if token_a {
+            (start.price_a_cumulative, current.price_a_cumulative)
+        } else {
+            (start.price_b_cumulative, current.price_b_cumulative)
+        };
+        end.wrapping_sub(start) / u128::from(window)
+    }
+
+    /// Accumulators at the current time, prices didn't change since the last update
+    fn current(&self, price_a: u128, price_b: u128) -> Observation {
+        let timestamp = now().max(self.last_timestamp);
+        let elapsed = u128::from(timestamp - self.last_timestamp);
+        Observation {
+            timestamp,
+            price_a_cumulative: self
+                .price_a_cumulative
+                .wrapping_add(price_a.wrapping_mul(elapsed)),
+            price_b_cumulative: self
+                .price_b_cumulative
+                .wrapping_add(price_b.wrapping_mul(elapsed)),
+        }
+    }
+
+    /// Interpolates accumulators at a `timestamp` between the closest observations,
+    /// the `current` one is used as the latest observation
+    fn cumulative_at(&self, current: Observation, timestamp: u64) -> Observation {
+        let len = self.observations.len();
+        let mut next = current;
+        // Observations are visited from the latest to the oldest one
+        for i in 0..len {
+            let index = (self.last_index + len - i) % len;
+            let observation = self.observations.get(index).unwrap();
+            if observation.timestamp <= timestamp {
+                return interpolate(observation, next, timestamp);
+            }
+            next = observation;
+        }
+
+        env::panic_str(
+            format!(
+                "Oracle doesn't have observations before {timestamp}, the oldest one is at {}",
+                next.timestamp
+            )
+            .as_str(),
+        )
+    }
+}
+
+/// Accumulators grow linearly between observations
+fn interpolate(before: Observation, after: Observation, timestamp: u64) -> Observation {
+    if after.timestamp == before.timestamp {
+        return before;
+    }
+
+    let elapsed = u128::from(timestamp - before.timestamp);
+    let duration = u128::from(after.timestamp - before.timestamp);
+    let growth = |before: u128, after: u128| {
+        let delta = after.wrapping_sub(before);
+        before.wrapping_add(
+            (U256::from(delta) * U256::from(elapsed) / U256::from(duration)).as_u128(),
+        )
+    };
+    Observation {
+        timestamp,
+        price_a_cumulative: growth(before.price_a_cumulative, after.price_a_cumulative),
+        price_b_cumulative: growth(before.price_b_cumulative, after.price_b_cumulative),
+    }
+}
+
+/// Price `numerator / denominator` in Q64.64, it's 0 for an empty pool and
+/// saturates instead of an overflow
+pub fn q64_price(numerator: u128, denominator: u128) -> u128 {
+    if denominator == 0 {
+        return 0;
+    }
+    let price = U256::from(numerator) * U256::from(Q64) / U256::from(denominator);
+    if price > U256::from(u128::MAX) {
+        u128::MAX
+    } else {
+        price.as_u128()
+    }
+}
+
+fn now() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    fn set_time(seconds: u64) {
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(seconds * 1_000_000_000)
+            .build());
+    }
+
+    #[test]
+    fn test_constant_price() {
+        set_time(1_000);
+        let mut oracle = Oracle::new();
+        set_time(1_100);
+        oracle.update(2 * Q64, Q64 / 2);
+
+        set_time(1_200);
+        assert_eq!(2 * Q64, oracle.consult(2 * Q64, Q64 / 2, true, 150));
+        assert_eq!(Q64 / 2, oracle.consult(2 * Q64, Q64 / 2, false, 150));
+    }
+
+    #[test]
+    fn test_time_weighted_average() {
+        set_time(1_000);
+        let mut oracle = Oracle::new();
+        oracle.update(Q64, Q64);
+
+        // The price is 1 for 300 seconds, then 4 for 100 seconds
+        set_time(1_300);
+        oracle.update(Q64, Q64);
+        set_time(1_400);
+        oracle.update(4 * Q64, Q64 / 4);
+
+        assert_eq!((300 + 400) * Q64 / 400, oracle.consult(Q64, Q64, true, 400));
+        // A window inside the first period is interpolated between observations
+        assert_eq!((100 + 400) * Q64 / 200, oracle.consult(Q64, Q64, true, 200));
+        // The current price is held since the last update
+        set_time(1_401);
+        assert_eq!(
+            (299 + 400 + 4) * Q64 / 400,
+            oracle.consult(4 * Q64, Q64 / 4, true, 400)
+        );
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_latest_observations() {
+        set_time(0);
+        let mut oracle = Oracle::new();
+        for i in 1..=MAX_OBSERVATIONS + 10 {
+            set_time(i * OBSERVATION_PERIOD);
+            oracle.update(Q64, Q64);
+        }
+        assert_eq!(MAX_OBSERVATIONS, oracle.observations.len());
+
+        let window = (MAX_OBSERVATIONS - 1) * OBSERVATION_PERIOD;
+        assert_eq!(Q64, oracle.consult(Q64, Q64, true, window));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_consult_window_longer_than_observations() {
+        set_time(1_000);
+        let mut oracle = Oracle::new();
+        set_time(1_100);
+        oracle.update(Q64, Q64);
+        oracle.consult(Q64, Q64, true, 200);
+    }
+
+    #[test]
+    fn test_q64_price() {
+        assert_eq!(Q64 / 2, q64_price(1, 2));
+        assert_eq!(0, q64_price(1, 0));
+        assert_eq!(u128::MAX, q64_price(u128::MAX, 1));
+    }
+}
@@ -0,0 +1,83 @@
+//! Emergency switches of the pool. The owner pauses each operation separately,
+//! or freezes everything except withdrawals with the withdraw-only mode.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AmmError;
+
+/// Operation that could be paused
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Operation {
+    /// Swaps of internal balances and swaps by transfer, including hops of routes
+    Swap,
+    /// `add_liquidity` and `open_position`
+    AddLiquidity,
+    /// Deposits with `ft_on_transfer`, transferred tokens are refunded
+    Deposit,
+    /// `withdraw_token`
+    Withdraw,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseState {
+    pub swap: bool,
+    pub add_liquidity: bool,
+    pub deposit: bool,
+    pub withdraw: bool,
+    /// Emergency mode, all operations are paused except withdrawals of internal balances
+    /// and removals of liquidity
+    pub withdraw_only: bool,
+}
+
+impl PauseState {
+    pub fn set(&mut self, operation: Operation, paused: bool) {
+        match operation {
+            Operation::Swap => self.swap = paused,
+            Operation::AddLiquidity => self.add_liquidity = paused,
+            Operation::Deposit => self.deposit = paused,
+            Operation::Withdraw => self.withdraw = paused,
+        }
+    }
+
+    /// The withdraw-only mode overrides switches of operations
+    pub fn is_paused(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Swap => self.swap || self.withdraw_only,
+            Operation::AddLiquidity => self.add_liquidity || self.withdraw_only,
+            Operation::Deposit => self.deposit || self.withdraw_only,
+            Operation::Withdraw => self.withdraw && !self.withdraw_only,
+        }
+    }
+
+    pub fn assert_not_paused(&self, operation: Operation) {
+        if self.is_paused(operation) {
+            AmmError::Paused { operation }.panic();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_withdraw_only_mode() {
+        let mut state = PauseState::default();
+        state.set(Operation::Withdraw, true);
+        assert!(state.is_paused(Operation::Withdraw));
+        assert!(!state.is_paused(Operation::Swap));
+
+        state.withdraw_only = true;
+        assert!(!state.is_paused(Operation::Withdraw));
+        assert!(state.is_paused(Operation::Swap));
+        assert!(state.is_paused(Operation::AddLiquidity));
+        assert!(state.is_paused(Operation::Deposit));
+
+        state.withdraw_only = false;
+        assert!(state.is_paused(Operation::Withdraw));
+        assert!(!state.is_paused(Operation::Deposit));
+    }
+}
@@ -0,0 +1,87 @@
+//! Role-based access control. The owner implicitly holds every role and its operators every
+//! role but the admin, other accounts are granted roles by admins, and each privileged method
+//! requires a specific role.
+
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::UnorderedSet,
+    AccountId,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Role {
+    /// Grants and revokes roles, changes the treasury and upgrades the code
+    Admin,
+    /// Changes the swap fee and the protocol fee
+    FeeManager,
+    /// Pauses operations and enables the withdraw-only mode
+    Pauser,
+    /// Changes parameters of the pool curve and the liquidity tolerance
+    LiquidityManager,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Roles {
+    members: UnorderedSet<(Role, AccountId)>,
+}
+
+impl Roles {
+    pub fn new(prefix: Vec<u8>) -> Self {
+        Self {
+            members: UnorderedSet::new(prefix),
+        }
+    }
+
+    /// Returns `false` if the account already holds the role
+    pub fn grant(&mut self, role: Role, account_id: AccountId) -> bool {
+        self.members.insert(&(role, account_id))
+    }
+
+    /// Returns `false` if the account doesn't hold the role
+    pub fn revoke(&mut self, role: Role, account_id: AccountId) -> bool {
+        self.members.remove(&(role, account_id))
+    }
+
+    pub fn has_role(&self, role: Role, account_id: &AccountId) -> bool {
+        self.members.contains(&(role, account_id.clone()))
+    }
+
+    /// Accounts that are granted a `role`, the owner is not included
+    pub fn members(&self, role: Role) -> Vec<AccountId> {
+        self.members
+            .iter()
+            .filter(|(member_role, _)| *member_role == role)
+            .map(|(_, account_id)| account_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    #[test]
+    fn test_grant_and_revoke() {
+        testing_env!(VMContextBuilder::new().build());
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let mut roles = Roles::new(b"r".to_vec());
+
+        assert!(roles.grant(Role::Pauser, alice.clone()));
+        assert!(!roles.grant(Role::Pauser, alice.clone()));
+        assert!(roles.grant(Role::FeeManager, bob.clone()));
+        assert!(roles.has_role(Role::Pauser, &alice));
+        assert!(!roles.has_role(Role::FeeManager, &alice));
+        assert_eq!(vec![bob.clone()], roles.members(Role::FeeManager));
+
+        assert!(roles.revoke(Role::Pauser, alice.clone()));
+        assert!(!roles.revoke(Role::Pauser, alice.clone()));
+        assert!(roles.members(Role::Pauser).is_empty());
+    }
+}
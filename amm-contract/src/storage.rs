@@ -0,0 +1,227 @@
+//! NEP-145 storage management. An account pays for its records in ledgers of tokens A and B
+//! and of LP shares before it holds any internal balance, so users can't spend the pool storage.
+
+use near_contract_standards::fungible_token::events::FtBurn;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{
+    assert_one_yocto, env, json_types::U128, log, near_bindgen, AccountId, Balance, Promise,
+};
+
+use crate::{AmmContract, AmmContractExt};
+
+#[near_bindgen]
+impl StorageManagement for AmmContract {
+    /// Registers an `account_id` or a caller in all ledgers of the pool. The minimum balance
+    /// is taken from the attached deposit and the rest is refunded, so `registration_only`
+    /// doesn't change anything.
+    #[allow(unused_variables)]
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount: Balance = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        if self.is_registered(&account_id) {
+            log!("The account is already registered, refunding the deposit");
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            if amount < min_balance {
+                env::panic_str("The attached deposit is less than the minimum storage balance");
+            }
+
+            self.token_a.try_register_account(&account_id);
+            self.token_b.try_register_account(&account_id);
+            self.shares.try_register_account(&account_id);
+            let refund = amount - min_balance;
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    /// The storage balance is always the minimum one, so nothing is available to withdraw
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let storage_balance = self
+            .storage_balance_of(account_id.clone())
+            .unwrap_or_else(|| {
+                env::panic_str(format!("The account {account_id} is not registered").as_str())
+            });
+        if amount.is_some_and(|amount| amount.0 > 0) {
+            env::panic_str("The amount is greater than the available storage balance");
+        }
+        storage_balance
+    }
+
+    /// Removes a caller from all ledgers and refunds the storage deposit. Internal balances
+    /// and LP shares should be withdrawn first, otherwise they are burned with `force`.
+    /// Concentrated liquidity positions are kept, the owner should register again to close them.
+    /// Accounts with pending withdrawals or hops of routes can't be unregistered even with `force`.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        if !self.is_registered(&account_id) {
+            log!("The account {} is not registered", account_id);
+            return false;
+        }
+        if self.pending_transfers.contains_key(&account_id) {
+            env::panic_str("Can't unregister the account with pending transfers");
+        }
+
+        let balance_a = self.token_a.balance_of(account_id.clone());
+        let balance_b = self.token_b.balance_of(account_id.clone());
+        let shares = self.shares.balance_of(account_id.clone());
+        let has_balance = balance_a.0 > 0 || balance_b.0 > 0 || shares.0 > 0;
+        if has_balance && !force.unwrap_or(false) {
+            env::panic_str("Can't unregister the account with the positive balance without force");
+        }
+
+        let refund = self.storage_balance_bounds().min.0;
+        for token in [&mut self.token_a, &mut self.token_b, &mut self.shares] {
+            let balance = token
+                .internal_token
+                .accounts
+                .remove(&account_id)
+                .unwrap_or(0);
+            token.internal_token.total_supply -= balance;
+        }
+        if shares.0 > 0 {
+            FtBurn {
+                owner_id: &account_id,
+                amount: &shares,
+                memo: Some("storage_unregister"),
+            }
+            .emit();
+        }
+        if has_balance {
+            log!(
+                "Balances of {} are burned, Token A: {:?}, Token B: {:?}",
+                account_id,
+                balance_a,
+                balance_b
+            );
+        }
+
+        Promise::new(account_id).transfer(refund);
+        true
+    }
+
+    /// An account takes the same storage in each of three ledgers
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let storage_usage = self.token_a.internal_token.account_storage_usage
+            + self.token_b.internal_token.account_storage_usage
+            + self.shares.internal_token.account_storage_usage;
+        let min = Balance::from(storage_usage) * env::storage_byte_cost();
+        StorageBalanceBounds {
+            min: min.into(),
+            max: Some(min.into()),
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        if !self.is_registered(&account_id) {
+            return None;
+        }
+        Some(StorageBalance {
+            total: self.storage_balance_bounds().min,
+            available: 0.into(),
+        })
+    }
+}
+
+impl AmmContract {
+    /// Check if an account paid for its storage in all ledgers of the pool
+    pub(crate) fn is_registered(&self, account_id: &AccountId) -> bool {
+        self.token_a.is_account_registered(account_id)
+            && self.token_b.is_account_registered(account_id)
+            && self.shares.is_account_registered(account_id)
+    }
+
+    pub(crate) fn assert_registered(&self, account_id: &AccountId) {
+        if !self.is_registered(account_id) {
+            env::panic_str(
+                format!("The account {account_id} is not registered, call storage_deposit first")
+                    .as_str(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::Curve;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env, ONE_YOCTO};
+
+    fn set_context(predecessor: &str, deposit: Balance) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("pool.near".parse().unwrap())
+            .predecessor_account_id(predecessor.parse().unwrap())
+            .attached_deposit(deposit)
+            .build());
+    }
+
+    fn pool() -> AmmContract {
+        set_context("owner.near", 0);
+        AmmContract::initialize(
+            "owner.near".parse().unwrap(),
+            "token-a.near".parse().unwrap(),
+            "token-b.near".parse().unwrap(),
+            30,
+            Some(Curve::ConstantProduct),
+        )
+    }
+
+    #[test]
+    fn test_storage_deposit_and_unregister() {
+        let mut pool = pool();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let min = pool.storage_balance_bounds().min;
+        assert!(pool.storage_balance_of(alice.clone()).is_none());
+
+        set_context("alice.near", min.0 * 2);
+        let balance = pool.storage_deposit(None, None);
+        assert_eq!(min, balance.total);
+        assert_eq!(U128::from(0), balance.available);
+        assert!(pool.is_registered(&alice));
+
+        set_context("alice.near", ONE_YOCTO);
+        assert!(pool.storage_unregister(None));
+        assert!(pool.storage_balance_of(alice.clone()).is_none());
+        assert!(!pool.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_storage_deposit_less_than_min() {
+        let mut pool = pool();
+        let min = pool.storage_balance_bounds().min;
+        set_context("alice.near", min.0 - 1);
+        pool.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_storage_unregister_with_balance() {
+        let mut pool = pool();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let min = pool.storage_balance_bounds().min;
+        set_context("alice.near", min.0);
+        pool.storage_deposit(None, None);
+        pool.token_a.deposit(&alice, 100.into());
+
+        set_context("alice.near", ONE_YOCTO);
+        pool.storage_unregister(None);
+    }
+}
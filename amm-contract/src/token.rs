@@ -4,10 +4,21 @@ use near_contract_standards::fungible_token::{
 
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
     json_types::U128,
-    near_bindgen, AccountId, PanicOnDefault,
+    near_bindgen, AccountId, Balance, PanicOnDefault,
 };
 
+pub use u256::U256;
+
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod u256 {
+    uint::construct_uint! {
+        /// 256-bit unsigned integer, used to avoid overflows in intermediate calculations
+        pub struct U256(4);
+    }
+}
+
 /// Structure that holds a [FungibleToken]
 /// Implements basic operations with a token
 #[near_bindgen]
@@ -16,6 +27,8 @@ pub struct Token {
     pub id: AccountId,
     pub internal_token: FungibleToken,
     pub metadata: Option<FungibleTokenMetadata>,
+    /// Accrued protocol fees, they are kept out of the pool until claimed by a treasury
+    pub protocol_fees: Balance,
 }
 
 impl Token {
@@ -24,6 +37,7 @@ impl Token {
             id,
             internal_token: FungibleToken::new(prefix),
             metadata: None,
+            protocol_fees: 0,
         }
     }
 
@@ -68,47 +82,382 @@ impl Token {
         }
     }
 
+    /// Credits an `amount` to a registered account, see [crate::storage]
     pub fn deposit(&mut self, sender_id: &AccountId, amount: U128) {
-        self.try_register_account(sender_id);
         self.internal_token
             .internal_deposit(sender_id, amount.into());
     }
 
+    /// Moves an `amount` from an account balance to the accrued protocol fees
+    pub fn accrue_protocol_fee(&mut self, account_id: &AccountId, amount: U128) {
+        if amount.0 == 0 {
+            return;
+        }
+        self.withdraw(account_id, amount);
+        self.protocol_fees += amount.0;
+    }
+
+    /// Credits all accrued protocol fees to a balance of a registered `treasury_id`
+    pub fn claim_protocol_fees(&mut self, treasury_id: &AccountId) -> U128 {
+        let amount = U128::from(self.protocol_fees);
+        if amount.0 > 0 {
+            self.deposit(treasury_id, amount);
+            self.protocol_fees = 0;
+        }
+        amount
+    }
+
     pub fn decimals(&self) -> Option<u8> {
         self.metadata.as_ref().map(|metadata| metadata.decimals)
     }
+
+    /// Converts an `amount` to a `precision`, that is not less than the token decimals
+    pub fn normalize(&self, amount: U128, precision: u8) -> u128 {
+        amount
+            .0
+            .checked_mul(self.precision_multiplier(precision))
+            .unwrap_or_else(|| env::panic_str("Normalized amount overflows"))
+    }
+
+    /// Converts an `amount` in a `precision` back to the token decimals, rounding down
+    pub fn denormalize(&self, amount: u128, precision: u8) -> U128 {
+        (amount / self.precision_multiplier(precision)).into()
+    }
+
+    /// Converts an `amount` in a `precision` back to the token decimals, rounding up
+    pub fn denormalize_ceil(&self, amount: u128, precision: u8) -> U128 {
+        amount.div_ceil(self.precision_multiplier(precision)).into()
+    }
+
+    fn precision_multiplier(&self, precision: u8) -> u128 {
+        let decimals = self.decimals().unwrap_or_else(|| {
+            env::panic_str(format!("Metadata for a token {} is empty", self.id).as_str())
+        });
+        10u128.pow((precision - decimals).into())
+    }
 }
 
-/// Implementation of a simple formula to not transfer more than we have
-pub fn calc_transfer_amount(src_pool_balance: U128, dst_pool_balance: U128, amount: U128) -> U128 {
-    let portion = src_pool_balance
-        .0
-        .checked_mul(dst_pool_balance.0)
-        .and_then(|it| it.checked_div(src_pool_balance.0 + amount.0))
-        .expect("Couldn't calculate a transaction amount");
+/// Denominator of fees, they are set in basis points
+pub const FEE_DIVISOR: u32 = 10_000;
+
+/// Implementation of a simple formula to not transfer more than we have.
+///
+/// The `fee` in basis points is deducted from the input `amount` before the calculation,
+/// so it's left in the pool.
+pub fn calc_transfer_amount(
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount: U128,
+    fee: u32,
+) -> U128 {
+    let amount_with_fee = mul_div(amount.0, (FEE_DIVISOR - fee).into(), FEE_DIVISOR.into());
+    let portion = mul_div(
+        src_pool_balance.0,
+        dst_pool_balance.0,
+        src_pool_balance.0 + amount_with_fee,
+    );
 
     dst_pool_balance.0.checked_sub(portion).unwrap().into()
 }
 
+/// Inverse of [calc_transfer_amount], calculates an input amount that's required
+/// to receive exactly `amount_out` with a `fee` in basis points.
+///
+/// Every step rounds up, so the rounding is always in favour of the pool.
+pub fn calc_amount_in(
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount_out: U128,
+    fee: u32,
+) -> U128 {
+    if amount_out >= dst_pool_balance {
+        env::panic_str(
+            format!(
+                "Pool doesn't have enough liquidity for {amount_out:?}, \
+                Pool balance: {dst_pool_balance:?}"
+            )
+            .as_str(),
+        );
+    }
+
+    let amount_with_fee = mul_div_ceil(
+        src_pool_balance.0,
+        amount_out.0,
+        dst_pool_balance.0 - amount_out.0,
+    );
+    mul_div_ceil(
+        amount_with_fee,
+        FEE_DIVISOR.into(),
+        (FEE_DIVISOR - fee).into(),
+    )
+    .into()
+}
+
+/// Calculates a part of the swap fee that goes to the protocol,
+/// `protocol_fee` is a fraction of the swap `fee` in basis points
+pub fn calc_protocol_fee(amount: U128, fee: u32, protocol_fee: u32) -> U128 {
+    let fee_amount = mul_div(amount.0, fee.into(), FEE_DIVISOR.into());
+    mul_div(fee_amount, protocol_fee.into(), FEE_DIVISOR.into()).into()
+}
+
+/// Calculates the amount of LP shares that should be minted for a provided liquidity.
+///
+/// The first deposit receives a geometric mean of the amounts, all next deposits
+/// receive shares pro-rata to the current pool balances.
+pub fn calc_shares(
+    liq_balance_a: U128,
+    liq_balance_b: U128,
+    amount_a: U128,
+    amount_b: U128,
+    total_shares: U128,
+) -> U128 {
+    if total_shares.0 == 0 {
+        return (U256::from(amount_a.0) * U256::from(amount_b.0))
+            .integer_sqrt()
+            .as_u128()
+            .into();
+    }
+
+    let shares_a = mul_div(amount_a.0, total_shares.0, liq_balance_a.0);
+    let shares_b = mul_div(amount_b.0, total_shares.0, liq_balance_b.0);
+    shares_a.min(shares_b).into()
+}
+
+/// Check if `amount_a` and `amount_b` are proportional to pool balances, they could deviate
+/// by a `tolerance` in basis points. Amounts are cross-multiplied, so nothing is truncated.
+pub fn is_proportional(
+    liq_balance_a: u128,
+    liq_balance_b: u128,
+    amount_a: u128,
+    amount_b: u128,
+    tolerance: u32,
+) -> bool {
+    let value_a = U256::from(amount_a) * U256::from(liq_balance_b);
+    let value_b = U256::from(amount_b) * U256::from(liq_balance_a);
+    let deviation = if value_a > value_b {
+        value_a - value_b
+    } else {
+        value_b - value_a
+    };
+    deviation * U256::from(FEE_DIVISOR) <= value_a.max(value_b) * U256::from(tolerance)
+}
+
+/// Calculates the largest amounts up to `max_amount_a` and `max_amount_b` that are
+/// proportional to pool balances, the derived amount is rounded down
+pub fn optimal_amounts(
+    liq_balance_a: u128,
+    liq_balance_b: u128,
+    max_amount_a: u128,
+    max_amount_b: u128,
+) -> (u128, u128) {
+    if U256::from(max_amount_a) * U256::from(liq_balance_b)
+        <= U256::from(max_amount_b) * U256::from(liq_balance_a)
+    {
+        let amount_b = mul_div(max_amount_a, liq_balance_b, liq_balance_a);
+        (max_amount_a, amount_b)
+    } else {
+        let amount_a = mul_div(max_amount_b, liq_balance_a, liq_balance_b);
+        (amount_a, max_amount_b)
+    }
+}
+
+/// Number of fractional digits of formatted ratios
+pub const RATIO_PRECISION: usize = 12;
+
+/// Formats `numerator / denominator` as a decimal string with up to [RATIO_PRECISION]
+/// fractional digits, trailing zeros are trimmed
+pub fn format_ratio(numerator: u128, denominator: u128) -> String {
+    if denominator == 0 {
+        return "0".to_string();
+    }
+
+    let integer = numerator / denominator;
+    let fraction = mul_div(
+        numerator % denominator,
+        10u128.pow(RATIO_PRECISION as u32),
+        denominator,
+    );
+    let fraction = format!("{fraction:0width$}", width = RATIO_PRECISION);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{integer}.{fraction}")
+    }
+}
+
+/// Calculates `a * b / c` without an intermediate overflow, rounding down
+pub fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+/// Calculates `a * b / c` without an intermediate overflow, rounding up
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> u128 {
+    let c = U256::from(c);
+    ((U256::from(a) * U256::from(b) + c - 1) / c).as_u128()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_calc_transfer_amount() {
-        let amount = calc_transfer_amount(6.into(), 30.into(), 1.into());
+        let amount = calc_transfer_amount(6.into(), 30.into(), 1.into(), 0);
         assert_eq!(U128::from(5), amount);
 
-        let amount = calc_transfer_amount(6.into(), 30.into(), 2.into());
+        let amount = calc_transfer_amount(6.into(), 30.into(), 2.into(), 0);
         assert_eq!(U128::from(8), amount);
     }
 
     #[test]
     fn test_calc_transfer_amount_from_greater_src() {
-        let amount = calc_transfer_amount(100.into(), 20.into(), 50.into());
+        let amount = calc_transfer_amount(100.into(), 20.into(), 50.into(), 0);
         assert_eq!(U128::from(7), amount);
 
-        let amount = calc_transfer_amount(100.into(), 20.into(), 10.into());
+        let amount = calc_transfer_amount(100.into(), 20.into(), 10.into(), 0);
         assert_eq!(U128::from(2), amount);
     }
+
+    #[test]
+    fn test_calc_transfer_amount_with_fee() {
+        let amount = calc_transfer_amount(1_000_000.into(), 1_000_000.into(), 100_000.into(), 0);
+        assert_eq!(U128::from(90_910), amount);
+
+        // 1% of the input amount stays in the pool
+        let amount = calc_transfer_amount(1_000_000.into(), 1_000_000.into(), 100_000.into(), 100);
+        assert_eq!(U128::from(90_082), amount);
+
+        let amount =
+            calc_transfer_amount(1_000_000.into(), 1_000_000.into(), 100_000.into(), 3_000);
+        assert_eq!(U128::from(65_421), amount);
+    }
+
+    #[test]
+    fn test_calc_transfer_amount_with_different_decimals() {
+        // 1000 tokens with 6 decimals and 1000 tokens with 24 decimals normalized to 24 decimals
+        let pool_balance = 1_000 * 10u128.pow(24);
+        let amount = calc_transfer_amount(
+            pool_balance.into(),
+            pool_balance.into(),
+            10u128.pow(24).into(),
+            0,
+        );
+        assert_eq!(U128::from(999_000_999_000_999_000_999_001), amount);
+    }
+
+    #[test]
+    fn test_is_proportional() {
+        // 25 A and 7 B are in the pool, 5 A and 15 B passed the truncated rate check
+        assert!(!is_proportional(25, 7, 5, 15, 100));
+        assert!(is_proportional(25, 7, 25, 7, 0));
+        assert!(!is_proportional(25, 7, 18, 5, 0));
+        assert!(is_proportional(25, 7, 18, 5, 100));
+
+        // Pools with more tokens B and with an empty side don't panic
+        assert!(is_proportional(7, 25, 7, 25, 0));
+        assert!(!is_proportional(25, 0, 5, 1, 100));
+    }
+
+    #[test]
+    fn test_optimal_amounts() {
+        assert_eq!((25, 7), optimal_amounts(25, 7, 25, 50));
+        assert_eq!((17, 5), optimal_amounts(25, 7, 50, 5));
+        assert_eq!((10, 0), optimal_amounts(25, 0, 10, 10));
+        assert_eq!((0, 10), optimal_amounts(0, 25, 10, 10));
+    }
+
+    #[test]
+    fn test_format_ratio() {
+        assert_eq!("1", format_ratio(1, 1));
+        assert_eq!("2.5", format_ratio(5, 2));
+        assert_eq!("0.333333333333", format_ratio(1, 3));
+        assert_eq!("0.000001", format_ratio(10u128.pow(18), 10u128.pow(24)));
+        assert_eq!("0", format_ratio(1, 0));
+    }
+
+    #[test]
+    fn test_calc_amount_in() {
+        let amount = calc_amount_in(6.into(), 30.into(), 5.into(), 0);
+        assert_eq!(U128::from(2), amount);
+
+        let amount = calc_amount_in(1_000_000.into(), 1_000_000.into(), 90_910.into(), 0);
+        assert_eq!(U128::from(100_002), amount);
+
+        let amount = calc_amount_in(1_000_000.into(), 1_000_000.into(), 90_082.into(), 100);
+        assert_eq!(U128::from(100_002), amount);
+    }
+
+    #[test]
+    fn test_calc_amount_in_favours_pool() {
+        let pools: [(u128, u128); 3] = [(6, 30), (100, 20), (1_000_000, 3_000_000)];
+        for (src_pool_balance, dst_pool_balance) in pools {
+            for amount_out in 1..dst_pool_balance.min(1_000) {
+                for fee in [0, 30, 100] {
+                    let amount_in = calc_amount_in(
+                        src_pool_balance.into(),
+                        dst_pool_balance.into(),
+                        amount_out.into(),
+                        fee,
+                    );
+                    let amount_with_fee =
+                        mul_div(amount_in.0, (FEE_DIVISOR - fee).into(), FEE_DIVISOR.into());
+
+                    // Constant product never decreases
+                    assert!(
+                        U256::from(src_pool_balance + amount_with_fee)
+                            * U256::from(dst_pool_balance - amount_out)
+                            >= U256::from(src_pool_balance) * U256::from(dst_pool_balance)
+                    );
+                    // The exact-input swap of the same amount isn't worse for a user
+                    let amount = calc_transfer_amount(
+                        src_pool_balance.into(),
+                        dst_pool_balance.into(),
+                        amount_in,
+                        fee,
+                    );
+                    assert!(amount.0 >= amount_out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_calc_amount_in_more_than_pool() {
+        calc_amount_in(6.into(), 30.into(), 30.into(), 0);
+    }
+
+    #[test]
+    fn test_calc_protocol_fee() {
+        // A fifth of 1% fee goes to the protocol
+        let amount = calc_protocol_fee(100_000.into(), 100, 2_000);
+        assert_eq!(U128::from(200), amount);
+
+        let amount = calc_protocol_fee(100_000.into(), 100, 0);
+        assert_eq!(U128::from(0), amount);
+
+        let amount = calc_protocol_fee(100_000.into(), 0, 2_000);
+        assert_eq!(U128::from(0), amount);
+    }
+
+    #[test]
+    fn test_calc_shares_first_deposit() {
+        let shares = calc_shares(0.into(), 0.into(), 30.into(), 6.into(), 0.into());
+        assert_eq!(U128::from(13), shares);
+
+        let shares = calc_shares(0.into(), 0.into(), 100.into(), 100.into(), 0.into());
+        assert_eq!(U128::from(100), shares);
+    }
+
+    #[test]
+    fn test_calc_shares_pro_rata() {
+        let shares = calc_shares(100.into(), 100.into(), 10.into(), 10.into(), 100.into());
+        assert_eq!(U128::from(10), shares);
+
+        // The less valuable side of an unbalanced deposit defines the shares
+        let shares = calc_shares(25.into(), 7.into(), 5.into(), 15.into(), 13.into());
+        assert_eq!(U128::from(2), shares);
+    }
 }
@@ -0,0 +1,322 @@
+//! Balancer-style weighted product math, the invariant is `X^Wx * Y^Wy = K`.
+//!
+//! There are no floats in a contract, so fractional powers are calculated in a fixed-point
+//! with 18 decimals, see [ONE]. A fractional part of an exponent is approximated by a binomial
+//! series up to [POW_PRECISION], that is `1e-10`. Results of swaps are shifted by [POW_ERROR],
+//! that is `1e-9`, so an approximation error is always in favour of the pool.
+
+use near_sdk::{env, json_types::U128};
+
+use crate::token::{mul_div, mul_div_ceil, FEE_DIVISOR, U256};
+
+/// `1.0` in the fixed-point
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+/// Precision of the binomial series of a fractional power
+const POW_PRECISION: u128 = ONE / 10_000_000_000;
+/// Upper bound of an error of the fractional power
+const POW_ERROR: u128 = ONE / 1_000_000_000;
+/// Denominator of weights, they are set in basis points
+pub const WEIGHT_DIVISOR: u32 = 10_000;
+/// Lower bound of a token weight, so the ratio of weights is at most 49
+pub const MIN_WEIGHT: u32 = 200;
+/// Shares that are minted for the first deposit of a weighted pool
+pub const INIT_POOL_SUPPLY: u128 = 100 * ONE;
+
+pub fn assert_valid_weights(weight_a: u32, weight_b: u32) {
+    if weight_a < MIN_WEIGHT || weight_b < MIN_WEIGHT || weight_a + weight_b != WEIGHT_DIVISOR {
+        env::panic_str(
+            format!(
+                "Weights {weight_a} and {weight_b} should be at least {MIN_WEIGHT} \
+                and their sum should be {WEIGHT_DIVISOR}"
+            )
+            .as_str(),
+        );
+    }
+}
+
+/// Weighted analogue of [crate::token::calc_transfer_amount],
+/// the `amount` shouldn't exceed a half of the source pool balance
+pub fn weighted_amount_out(
+    src_weight: u32,
+    dst_weight: u32,
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount: U128,
+    fee: u32,
+) -> U128 {
+    if amount.0 > src_pool_balance.0 / 2 {
+        env::panic_str(
+            format!(
+                "Swap amount {amount:?} exceeds a half of the pool balance {src_pool_balance:?}"
+            )
+            .as_str(),
+        );
+    }
+
+    let amount_with_fee = mul_div(amount.0, (FEE_DIVISOR - fee).into(), FEE_DIVISOR.into());
+    let weight_ratio = mul_div(src_weight.into(), ONE, dst_weight.into());
+    let base = mul_div_ceil(
+        src_pool_balance.0,
+        ONE,
+        src_pool_balance.0 + amount_with_fee,
+    );
+
+    let power = pow(base, weight_ratio) + POW_ERROR;
+    if power >= ONE {
+        return 0.into();
+    }
+    mul_div(dst_pool_balance.0, ONE - power, ONE).into()
+}
+
+/// Weighted analogue of [crate::token::calc_amount_in],
+/// the `amount_out` shouldn't exceed a third of the destination pool balance
+pub fn weighted_amount_in(
+    src_weight: u32,
+    dst_weight: u32,
+    src_pool_balance: U128,
+    dst_pool_balance: U128,
+    amount_out: U128,
+    fee: u32,
+) -> U128 {
+    if amount_out.0 > dst_pool_balance.0 / 3 {
+        env::panic_str(
+            format!(
+                "Swap output {amount_out:?} exceeds a third of the pool balance \
+                {dst_pool_balance:?}"
+            )
+            .as_str(),
+        );
+    }
+
+    let weight_ratio = mul_div(dst_weight.into(), ONE, src_weight.into());
+    let base = mul_div_ceil(dst_pool_balance.0, ONE, dst_pool_balance.0 - amount_out.0);
+
+    let power = pow(base, weight_ratio) - ONE + POW_ERROR;
+    let amount_with_fee = mul_div_ceil(src_pool_balance.0, power, ONE);
+    mul_div_ceil(
+        amount_with_fee,
+        FEE_DIVISOR.into(),
+        (FEE_DIVISOR - fee).into(),
+    )
+    .into()
+}
+
+/// Calculates `base^exp` in the fixed-point, the `base` should be in range `(0, 2)`
+pub fn pow(base: u128, exp: u128) -> u128 {
+    let whole = exp / ONE;
+    let remain = exp % ONE;
+
+    let whole_pow = pow_int(base, whole);
+    if remain == 0 {
+        return whole_pow;
+    }
+    mul(whole_pow, pow_approx(base, remain))
+}
+
+/// Calculates `base^exp` for an integer `exp` by squaring
+fn pow_int(mut base: u128, mut exp: u128) -> u128 {
+    let mut result = if exp % 2 == 1 { base } else { ONE };
+    exp /= 2;
+    while exp > 0 {
+        base = mul(base, base);
+        if exp % 2 == 1 {
+            result = mul(result, base);
+        }
+        exp /= 2;
+    }
+    result
+}
+
+/// Approximates `base^exp` for a fractional `exp` with a binomial series of `(1 + x)^exp`
+fn pow_approx(base: u128, exp: u128) -> u128 {
+    let (x, x_negative) = sub_sign(base, ONE);
+    let mut term = ONE;
+    let mut sum = ONE;
+    let mut negative = false;
+
+    let mut k = 1;
+    while term >= POW_PRECISION {
+        let big_k = k * ONE;
+        let (c, c_negative) = sub_sign(exp, big_k - ONE);
+        term = div(mul(term, mul(c, x)), big_k);
+        if term == 0 {
+            break;
+        }
+
+        if x_negative {
+            negative = !negative;
+        }
+        if c_negative {
+            negative = !negative;
+        }
+        if negative {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        k += 1;
+    }
+    sum
+}
+
+/// Multiplies two fixed-point numbers, rounding half up
+fn mul(a: u128, b: u128) -> u128 {
+    ((U256::from(a) * U256::from(b) + U256::from(ONE / 2)) / U256::from(ONE)).as_u128()
+}
+
+/// Divides two fixed-point numbers, rounding half up
+fn div(a: u128, b: u128) -> u128 {
+    ((U256::from(a) * U256::from(ONE) + U256::from(b / 2)) / U256::from(b)).as_u128()
+}
+
+fn sub_sign(a: u128, b: u128) -> (u128, bool) {
+    if a >= b {
+        (a - b, false)
+    } else {
+        (b - a, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::calc_transfer_amount;
+
+    /// Checks `(x + dx)^Wx * (y - dy)^Wy >= x^Wx * y^Wy` with weights in percents
+    fn assert_invariant_holds(
+        src_weight: u32,
+        src_pool_balance: u128,
+        dst_pool_balance: u128,
+        amount_in: u128,
+        amount_out: u128,
+    ) {
+        let src_exp = src_weight / 100;
+        let dst_exp = 100 - src_exp;
+        let (mut a, mut b) = (src_exp, dst_exp);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        let (src_exp, dst_exp) = (src_exp / a, dst_exp / a);
+
+        // Balances of tests are small enough, so powers fit into 256 bits
+        let k = |x: u128, y: u128| {
+            U256::from(x).pow(src_exp.into()) * U256::from(y).pow(dst_exp.into())
+        };
+        assert!(
+            k(src_pool_balance + amount_in, dst_pool_balance - amount_out)
+                >= k(src_pool_balance, dst_pool_balance)
+        );
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(ONE, pow(ONE, ONE / 4));
+        assert_eq!(ONE / 16, pow(ONE / 2, 4 * ONE));
+
+        // 0.5^0.5 = 0.70710678118...
+        let result = pow(ONE / 2, ONE / 2);
+        assert!(result.abs_diff(707_106_781_186_547_524) < POW_PRECISION);
+
+        // 1.5^2.5 = 2.75567596...
+        let result = pow(3 * ONE / 2, 5 * ONE / 2);
+        assert!(result.abs_diff(2_755_675_960_631_075_360) < POW_PRECISION * 10);
+    }
+
+    #[test]
+    fn test_equal_weights_match_constant_product() {
+        let amount = weighted_amount_out(
+            5_000,
+            5_000,
+            1_000_000.into(),
+            1_000_000.into(),
+            100_000.into(),
+            0,
+        );
+        let constant_product_amount =
+            calc_transfer_amount(1_000_000.into(), 1_000_000.into(), 100_000.into(), 0);
+        assert_eq!(U128::from(90_909), amount);
+        assert!(amount <= constant_product_amount);
+    }
+
+    #[test]
+    fn test_weighted_amount_out() {
+        // 1 - (1 / 1.1)^4 of the destination balance
+        let amount = weighted_amount_out(
+            8_000,
+            2_000,
+            1_000_000.into(),
+            1_000_000.into(),
+            100_000.into(),
+            0,
+        );
+        assert_eq!(U128::from(316_986), amount);
+
+        // 1 - (1 / 1.1)^0.25 of the destination balance
+        let amount = weighted_amount_out(
+            2_000,
+            8_000,
+            1_000_000.into(),
+            1_000_000.into(),
+            100_000.into(),
+            0,
+        );
+        assert_eq!(U128::from(23_545), amount);
+    }
+
+    #[test]
+    fn test_weighted_swaps_keep_invariant() {
+        let pools: [(u128, u128); 3] = [(1_000, 3_000), (3_000, 20), (20, 3_000)];
+        for (src_pool_balance, dst_pool_balance) in pools {
+            for src_weight in [500, 2_000, 5_000, 8_000, 9_500] {
+                let dst_weight = WEIGHT_DIVISOR - src_weight;
+                for amount in [1, src_pool_balance / 10, src_pool_balance / 2] {
+                    let amount_out = weighted_amount_out(
+                        src_weight,
+                        dst_weight,
+                        src_pool_balance.into(),
+                        dst_pool_balance.into(),
+                        amount.into(),
+                        0,
+                    );
+                    assert_invariant_holds(
+                        src_weight,
+                        src_pool_balance,
+                        dst_pool_balance,
+                        amount,
+                        amount_out.0,
+                    );
+                }
+
+                for amount_out in [1, dst_pool_balance / 10, dst_pool_balance / 3] {
+                    let amount_in = weighted_amount_in(
+                        src_weight,
+                        dst_weight,
+                        src_pool_balance.into(),
+                        dst_pool_balance.into(),
+                        amount_out.into(),
+                        0,
+                    );
+                    assert_invariant_holds(
+                        src_weight,
+                        src_pool_balance,
+                        dst_pool_balance,
+                        amount_in.0,
+                        amount_out,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_weighted_amount_out_more_than_half() {
+        weighted_amount_out(8_000, 2_000, 100.into(), 100.into(), 51.into(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_weights() {
+        assert_valid_weights(8_000, 1_000);
+    }
+}
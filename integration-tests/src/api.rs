@@ -46,15 +46,37 @@ pub async fn ft_balance_of(
         .json()
 }
 
+pub async fn ft_total_supply(
+    worker: &Worker<Sandbox>,
+    token_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<U128> {
+    user.call(worker, token_contract.id(), "ft_total_supply")
+        .transact()
+        .await?
+        .json()
+}
+
 pub async fn ft_transfer_call(
     worker: &Worker<Sandbox>,
     token_contract: &Contract,
     from: &Account,
     to: &Account,
     amount: U128,
+) -> anyhow::Result<()> {
+    ft_transfer_call_with_msg(worker, token_contract, from, to, amount, "").await
+}
+
+pub async fn ft_transfer_call_with_msg(
+    worker: &Worker<Sandbox>,
+    token_contract: &Contract,
+    from: &Account,
+    to: &Account,
+    amount: U128,
+    msg: &str,
 ) -> anyhow::Result<()> {
     from.call(worker, token_contract.id(), "ft_transfer_call")
-        .args_json(serde_json::json!({"receiver_id": to.id(), "amount": amount, "msg": "0"}))?
+        .args_json(serde_json::json!({"receiver_id": to.id(), "amount": amount, "msg": msg}))?
         .max_gas()
         .deposit(1)
         .transact()
@@ -70,7 +92,7 @@ pub async fn add_liquidity(
     amount_liq_a: U128,
     token_b_id: &AccountId,
     amount_liq_b: U128,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<U128> {
     owner
         .call(worker, amm_contract.id(), "add_liquidity")
         .args_json(serde_json::json!({
@@ -81,8 +103,54 @@ pub async fn add_liquidity(
         }))?
         .max_gas()
         .transact()
-        .await?;
-    Ok(())
+        .await?
+        .json()
+}
+
+/// Same as [add_liquidity], but amounts are maximums and the largest proportional
+/// amounts are deposited
+pub async fn add_liquidity_optimal(
+    worker: &Worker<Sandbox>,
+    owner: &Account,
+    amm_contract: &Contract,
+    token_a_id: &AccountId,
+    max_amount_a: U128,
+    token_b_id: &AccountId,
+    max_amount_b: U128,
+) -> anyhow::Result<U128> {
+    owner
+        .call(worker, amm_contract.id(), "add_liquidity")
+        .args_json(serde_json::json!({
+            "token_a_id": token_a_id,
+            "amount_liq_a": max_amount_a,
+            "token_b_id": token_b_id,
+            "amount_liq_b": max_amount_b,
+            "optimal": true,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn remove_liquidity(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    shares: U128,
+    min_amount_a: U128,
+    min_amount_b: U128,
+) -> anyhow::Result<(U128, U128)> {
+    user.call(worker, amm_contract.id(), "remove_liquidity")
+        .args_json(serde_json::json!({
+            "shares": shares,
+            "min_amount_a": min_amount_a,
+            "min_amount_b": min_amount_b,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
 }
 
 pub async fn swap(
@@ -92,17 +160,41 @@ pub async fn swap(
     from: &AccountId,
     to: &AccountId,
     amount: U128,
-) -> anyhow::Result<()> {
+    min_amount_out: Option<U128>,
+) -> anyhow::Result<U128> {
     user.call(worker, amm_contract.id(), "swap")
         .args_json(serde_json::json!({
             "from_token_id": from,
             "to_token_id": to,
             "amount": amount,
+            "min_amount_out": min_amount_out,
         }))?
         .max_gas()
         .transact()
-        .await?;
-    Ok(())
+        .await?
+        .json()
+}
+
+pub async fn swap_exact_out(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    from: &AccountId,
+    to: &AccountId,
+    amount_out: U128,
+    max_amount_in: U128,
+) -> anyhow::Result<U128> {
+    user.call(worker, amm_contract.id(), "swap_exact_out")
+        .args_json(serde_json::json!({
+            "from_token_id": from,
+            "to_token_id": to,
+            "amount_out": amount_out,
+            "max_amount_in": max_amount_in,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
 }
 
 pub async fn withdraw_token(
@@ -124,6 +216,115 @@ pub async fn withdraw_token(
     Ok(())
 }
 
+pub async fn storage_unregister(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    force: bool,
+) -> anyhow::Result<bool> {
+    user.call(worker, amm_contract.id(), "storage_unregister")
+        .args_json(serde_json::json!({ "force": force }))?
+        .max_gas()
+        .deposit(1)
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn storage_balance_of(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "storage_balance_of")
+        .args_json(serde_json::json!({ "account_id": user.id() }))?
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn exchange_rate(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<String> {
+    user.call(worker, amm_contract.id(), "exchange_rate")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn pool_info(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "pool_info")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn get_amount_out(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    from: &AccountId,
+    to: &AccountId,
+    amount: U128,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "get_amount_out")
+        .args_json(serde_json::json!({
+            "from_token_id": from,
+            "to_token_id": to,
+            "amount": amount,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn get_amount_in(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    from: &AccountId,
+    to: &AccountId,
+    amount_out: U128,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "get_amount_in")
+        .args_json(serde_json::json!({
+            "from_token_id": from,
+            "to_token_id": to,
+            "amount_out": amount_out,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn consult(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    token_id: &AccountId,
+    window_seconds: u64,
+) -> anyhow::Result<String> {
+    user.call(worker, amm_contract.id(), "consult")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "window_seconds": window_seconds,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
 #[allow(dead_code)]
 pub async fn token_a_supply(
     worker: &Worker<Sandbox>,
@@ -174,7 +375,6 @@ pub async fn token_b_in_pool(
         .json()
 }
 
-#[allow(dead_code)]
 pub async fn balance_of_token_b(
     worker: &Worker<Sandbox>,
     amm_contract: &Contract,
@@ -200,3 +400,489 @@ pub async fn balance_of_token_a(
         .await?
         .json()
 }
+
+pub async fn set_fee(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    fee: u32,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_fee")
+        .args_json(serde_json::json!({ "fee": fee }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn fee(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<u32> {
+    user.call(worker, amm_contract.id(), "fee")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn set_protocol_fee(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    protocol_fee: u32,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_protocol_fee")
+        .args_json(serde_json::json!({ "protocol_fee": protocol_fee }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn set_treasury(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    treasury_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_treasury")
+        .args_json(serde_json::json!({ "treasury_id": treasury_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn grant_role(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    role: &str,
+    account_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "grant_role")
+        .args_json(serde_json::json!({ "role": role, "account_id": account_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn revoke_role(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    role: &str,
+    account_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "revoke_role")
+        .args_json(serde_json::json!({ "role": role, "account_id": account_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_role_members(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    role: &str,
+) -> anyhow::Result<Vec<AccountId>> {
+    user.call(worker, amm_contract.id(), "get_role_members")
+        .args_json(serde_json::json!({ "role": role }))?
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn approve_operator(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    operator_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "approve_operator")
+        .args_json(serde_json::json!({ "operator_id": operator_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn revoke_operator(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    operator_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "revoke_operator")
+        .args_json(serde_json::json!({ "operator_id": operator_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_operators(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<Vec<AccountId>> {
+    user.call(worker, amm_contract.id(), "get_operators")
+        .transact()
+        .await?
+        .json()
+}
+
+/// Calls a `method_name` of an `amm_contract` through a `proxy_contract`, so the pool sees
+/// the proxy as a caller while the `user` signs the transaction
+pub async fn proxy_call(
+    worker: &Worker<Sandbox>,
+    proxy_contract: &Contract,
+    user: &Account,
+    amm_contract: &Contract,
+    method_name: &str,
+    args: serde_json::Value,
+) -> anyhow::Result<()> {
+    user.call(worker, proxy_contract.id(), "call")
+        .args_json(serde_json::json!({
+            "contract_id": amm_contract.id(),
+            "method_name": method_name,
+            "args": args.to_string(),
+        }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn set_paused(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    operation: &str,
+    paused: bool,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_paused")
+        .args_json(serde_json::json!({ "operation": operation, "paused": paused }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn set_withdraw_only(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_withdraw_only")
+        .args_json(serde_json::json!({ "enabled": enabled }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn propose_owner(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    new_owner_id: &AccountId,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "propose_owner")
+        .args_json(serde_json::json!({ "new_owner_id": new_owner_id }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn accept_owner(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "accept_owner")
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_owner(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<AccountId> {
+    user.call(worker, amm_contract.id(), "get_owner")
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn claim_protocol_fees(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<(U128, U128)> {
+    user.call(worker, amm_contract.id(), "claim_protocol_fees")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn token_a_protocol_fees(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<U128> {
+    user.call(worker, amm_contract.id(), "token_a_protocol_fees")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn set_amp(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    amp: u64,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_amp")
+        .args_json(serde_json::json!({ "amp": amp }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn set_liquidity_tolerance(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    tolerance: u32,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "set_liquidity_tolerance")
+        .args_json(serde_json::json!({ "tolerance": tolerance }))?
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn curve(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "curve")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn open_position(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    lower_tick: i32,
+    upper_tick: i32,
+    amount_a: U128,
+    amount_b: U128,
+) -> anyhow::Result<u64> {
+    user.call(worker, amm_contract.id(), "open_position")
+        .args_json(serde_json::json!({
+            "lower_tick": lower_tick,
+            "upper_tick": upper_tick,
+            "amount_a": amount_a,
+            "amount_b": amount_b,
+        }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn close_position(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    position_id: u64,
+) -> anyhow::Result<(U128, U128)> {
+    user.call(worker, amm_contract.id(), "close_position")
+        .args_json(serde_json::json!({ "position_id": position_id }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn get_position(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    position_id: u64,
+) -> anyhow::Result<serde_json::Value> {
+    user.call(worker, amm_contract.id(), "get_position")
+        .args_json(serde_json::json!({ "position_id": position_id }))?
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn current_tick(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<i32> {
+    user.call(worker, amm_contract.id(), "current_tick")
+        .max_gas()
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn upgrade(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+    code: Vec<u8>,
+) -> anyhow::Result<()> {
+    user.call(worker, amm_contract.id(), "upgrade")
+        .args(code)
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn state_version(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<u8> {
+    user.call(worker, amm_contract.id(), "state_version")
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn liquidity_tolerance(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    user: &Account,
+) -> anyhow::Result<u32> {
+    user.call(worker, amm_contract.id(), "liquidity_tolerance")
+        .transact()
+        .await?
+        .json()
+}
+
+/// Raw state of a contract that is stored under the `STATE` key
+pub async fn contract_state(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+) -> anyhow::Result<Vec<u8>> {
+    worker
+        .view_state(amm_contract.id(), Some(b"STATE"))
+        .await?
+        .remove(b"STATE".as_slice())
+        .ok_or_else(|| anyhow::anyhow!("Contract state is missing"))
+}
+
+/// Overwrites a raw state of a pool and its layout version, so the pool looks like
+/// it was deployed by a previous code
+pub async fn patch_pool_state(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    state: &[u8],
+    state_version: u8,
+) -> anyhow::Result<()> {
+    worker
+        .patch_state(amm_contract.id(), b"STATE", state)
+        .await?;
+    worker
+        .patch_state(amm_contract.id(), b"VERSION", &[state_version])
+        .await?;
+    Ok(())
+}
+
+pub async fn set_pool_code(
+    worker: &Worker<Sandbox>,
+    factory_contract: &Contract,
+    user: &Account,
+    code: Vec<u8>,
+) -> anyhow::Result<()> {
+    user.call(worker, factory_contract.id(), "set_pool_code")
+        .args(code)
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(())
+}
+
+pub async fn create_pool(
+    worker: &Worker<Sandbox>,
+    factory_contract: &Contract,
+    user: &Account,
+    token_a_id: &AccountId,
+    token_b_id: &AccountId,
+    fee: u32,
+    curve: Option<serde_json::Value>,
+) -> anyhow::Result<bool> {
+    user.call(worker, factory_contract.id(), "create_pool")
+        .args_json(serde_json::json!({
+            "token_a_id": token_a_id,
+            "token_b_id": token_b_id,
+            "fee": fee,
+            "curve": curve,
+        }))?
+        .max_gas()
+        .deposit(parse_near!("6 N"))
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn get_pool(
+    worker: &Worker<Sandbox>,
+    factory_contract: &Contract,
+    user: &Account,
+    token_a_id: &AccountId,
+    token_b_id: &AccountId,
+) -> anyhow::Result<Option<AccountId>> {
+    user.call(worker, factory_contract.id(), "get_pool")
+        .args_json(serde_json::json!({
+            "token_a_id": token_a_id,
+            "token_b_id": token_b_id,
+        }))?
+        .transact()
+        .await?
+        .json()
+}
+
+pub async fn get_pools(
+    worker: &Worker<Sandbox>,
+    factory_contract: &Contract,
+    user: &Account,
+    from_index: u64,
+    limit: u64,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    user.call(worker, factory_contract.id(), "get_pools")
+        .args_json(serde_json::json!({
+            "from_index": from_index,
+            "limit": limit,
+        }))?
+        .transact()
+        .await?
+        .json()
+}
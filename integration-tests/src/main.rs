@@ -7,8 +7,12 @@ use workspaces::prelude::*;
 use workspaces::{network::Sandbox, Account, Contract, Worker};
 
 const WASM_FILEPATH_CONTRACT: &str = "target/wasm32-unknown-unknown/release/amm_contract.wasm";
+const WASM_FILEPATH_POOL_FACTORY: &str = "target/wasm32-unknown-unknown/release/pool_factory.wasm";
 const WASM_FILEPATH_TOKEN_A: &str = "target/wasm32-unknown-unknown/release/token_a.wasm";
 const WASM_FILEPATH_TOKEN_B: &str = "target/wasm32-unknown-unknown/release/token_b.wasm";
+const WASM_FILEPATH_TOKEN_C: &str = "target/wasm32-unknown-unknown/release/token_c.wasm";
+const WASM_FILEPATH_TOKEN_D: &str = "target/wasm32-unknown-unknown/release/token_d.wasm";
+const WASM_FILEPATH_PROXY: &str = "target/wasm32-unknown-unknown/release/proxy.wasm";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -59,6 +63,7 @@ async fn main() -> anyhow::Result<()> {
             "owner_id": owner.id(),
             "token_a_id": token_a_contract.id(),
             "token_b_id": token_b_contract.id(),
+            "fee": 0,
         }))?
         .max_gas()
         .transact()
@@ -69,6 +74,7 @@ async fn main() -> anyhow::Result<()> {
         &amm_contract,
         &token_a_contract,
         &token_b_contract,
+        &owner,
         &alice,
     )
     .await?;
@@ -128,7 +134,18 @@ async fn main() -> anyhow::Result<()> {
         &alice,
     )
     .await?;
-    test_add_liquidity_with_wrong_owner(
+    test_add_liquidity_with_wrong_tokens(
+        &worker,
+        &amm_contract,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+    )
+    .await?;
+    test_shares_transfer(&worker, &amm_contract, &owner, &alice).await?;
+    test_remove_liquidity_below_min_amounts(&worker, &amm_contract, &alice).await?;
+    test_remove_liquidity(&worker, &amm_contract, &alice).await?;
+    test_add_liquidity_by_provider(
         &worker,
         &amm_contract,
         &token_a_contract,
@@ -136,12 +153,109 @@ async fn main() -> anyhow::Result<()> {
         &alice,
     )
     .await?;
-    test_add_liquidity_with_wrong_tokens(
+    test_set_fee(&worker, &amm_contract, &owner, &alice).await?;
+    test_swap_with_slippage(
+        &worker,
+        &amm_contract,
+        &token_a_contract,
+        &token_b_contract,
+        &alice,
+    )
+    .await?;
+    test_swap_exact_out(
+        &worker,
+        &amm_contract,
+        &token_a_contract,
+        &token_b_contract,
+        &alice,
+    )
+    .await?;
+    test_swap_on_transfer(
         &worker,
         &amm_contract,
         &token_a_contract,
         &token_b_contract,
+        &alice,
+    )
+    .await?;
+    test_add_liquidity_optimal(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_protocol_fees(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_pool_with_different_decimals(&worker, &owner).await?;
+    test_pool_factory(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_route_swap(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_stable_swap_pool(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_weighted_pool(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_concentrated_liquidity(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_price_oracle(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_quotes(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_pool_info(&worker, &token_a_contract, &token_b_contract, &owner).await?;
+    test_storage_management(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_ownership_transfer(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_pause(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_upgrade(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_roles(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
+        &owner,
+        &alice,
+    )
+    .await?;
+    test_operators(
+        &worker,
+        &token_a_contract,
+        &token_b_contract,
         &owner,
+        &alice,
     )
     .await?;
 
@@ -214,6 +328,10 @@ async fn test_add_liquidity(
     assert_eq!(U128::from(30), amount_token_a);
     assert_eq!(U128::from(6), amount_token_b);
 
+    // The first deposit mints sqrt(30 * 6) shares
+    let shares = api::ft_balance_of(worker, owner, amm_contract.id()).await?;
+    assert_eq!(U128::from(13), shares);
+
     println!("      Passed ✅ test_add_liquidity");
     Ok(())
 }
@@ -246,6 +364,7 @@ async fn test_swap_wrong_amount(
         token_b_contract.id(),
         token_a_contract.id(),
         5.into(),
+        None,
     )
     .await;
 
@@ -269,6 +388,7 @@ async fn test_swap_correct_amount(
         token_b_contract.id(),
         token_a_contract.id(),
         1.into(),
+        None,
     )
     .await?;
 
@@ -351,81 +471,1703 @@ async fn test_add_liquidity_correct_again(
     owner: &Account,
     alice: &Account,
 ) -> anyhow::Result<()> {
+    // Pool holds 25 A and 7 B, a top-up should be proportional to it
     api::add_liquidity(
         worker,
         owner,
         amm_contract,
         token_a_contract.id(),
-        5.into(),
+        25.into(),
         token_b_contract.id(),
-        15.into(),
+        7.into(),
     )
     .await?;
 
-    // Check pool after swap
     let amount_token_a = api::token_a_in_pool(worker, amm_contract, alice).await?;
     let amount_token_b = api::token_b_in_pool(worker, amm_contract, alice).await?;
+    let balance_b = api::balance_of_token_b(worker, amm_contract, owner).await?;
 
-    assert_eq!(U128::from(30), amount_token_a);
-    assert_eq!(U128::from(22), amount_token_b);
+    assert_eq!(U128::from(50), amount_token_a);
+    assert_eq!(U128::from(14), amount_token_b);
+    assert_eq!(U128::from(43), balance_b);
+
+    // Shares are minted pro-rata to the pool balances: 25 * 13 / 25
+    let shares = api::ft_balance_of(worker, owner, amm_contract.id()).await?;
+    assert_eq!(U128::from(26), shares);
 
     println!("      Passed ✅ test_add_liquidity_correct_again");
     Ok(())
 }
 
-async fn test_add_liquidity_with_wrong_owner(
+async fn test_add_liquidity_with_wrong_tokens(
     worker: &Worker<Sandbox>,
     amm_contract: &Contract,
     token_a_contract: &Contract,
     token_b_contract: &Contract,
-    alice: &Account,
+    owner: &Account,
 ) -> anyhow::Result<()> {
     let res = api::add_liquidity(
         worker,
-        alice,
+        owner,
         amm_contract,
-        token_a_contract.id(),
-        30.into(),
         token_b_contract.id(),
+        30.into(),
+        token_a_contract.id(),
         6.into(),
     )
     .await;
 
-    matches!(res, Result::Err(err) if err.to_string().as_str() == "Access unauthorized");
-    println!("      Passed ✅ test_add_liquidity_with_wrong_owner");
+    assert!(res.is_err());
+
+    println!("      Passed ✅ test_add_liquidity_with_wrong_tokens");
     Ok(())
 }
 
-async fn test_add_liquidity_with_wrong_tokens(
+async fn test_shares_transfer(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    api::ft_transfer(worker, amm_contract, owner, alice, 13.into()).await?;
+
+    let owner_shares = api::ft_balance_of(worker, owner, amm_contract.id()).await?;
+    let alice_shares = api::ft_balance_of(worker, alice, amm_contract.id()).await?;
+    let total_shares = api::ft_total_supply(worker, amm_contract, alice).await?;
+
+    assert_eq!(U128::from(13), owner_shares);
+    assert_eq!(U128::from(13), alice_shares);
+    assert_eq!(U128::from(26), total_shares);
+
+    println!("      Passed ✅ test_shares_transfer");
+    Ok(())
+}
+
+async fn test_remove_liquidity_below_min_amounts(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let res =
+        api::remove_liquidity(worker, amm_contract, alice, 13.into(), 26.into(), 7.into()).await;
+
+    assert!(res.is_err());
+
+    println!("      Passed ✅ test_remove_liquidity_below_min_amounts");
+    Ok(())
+}
+
+async fn test_remove_liquidity(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    // Pool holds 50 A and 14 B for 26 shares, so 13 shares are worth 25 A and 7 B
+    api::remove_liquidity(worker, amm_contract, alice, 13.into(), 25.into(), 7.into()).await?;
+
+    let balance_a = api::balance_of_token_a(worker, amm_contract, alice).await?;
+    let balance_b = api::balance_of_token_b(worker, amm_contract, alice).await?;
+    let alice_shares = api::ft_balance_of(worker, alice, amm_contract.id()).await?;
+    let amount_token_a = api::token_a_in_pool(worker, amm_contract, alice).await?;
+    let amount_token_b = api::token_b_in_pool(worker, amm_contract, alice).await?;
+
+    assert_eq!(U128::from(25), balance_a);
+    assert_eq!(U128::from(7), balance_b);
+    assert_eq!(U128::from(0), alice_shares);
+    assert_eq!(U128::from(25), amount_token_a);
+    assert_eq!(U128::from(7), amount_token_b);
+
+    println!("      Passed ✅ test_remove_liquidity");
+    Ok(())
+}
+
+async fn test_add_liquidity_by_provider(
     worker: &Worker<Sandbox>,
     amm_contract: &Contract,
     token_a_contract: &Contract,
     token_b_contract: &Contract,
-    owner: &Account,
+    alice: &Account,
 ) -> anyhow::Result<()> {
-    let res = api::add_liquidity(
+    // Not only the owner is able to provide a liquidity
+    let shares = api::add_liquidity(
         worker,
-        owner,
+        alice,
         amm_contract,
+        token_a_contract.id(),
+        18.into(),
         token_b_contract.id(),
-        30.into(),
+        5.into(),
+    )
+    .await?;
+
+    let alice_shares = api::ft_balance_of(worker, alice, amm_contract.id()).await?;
+    let total_shares = api::ft_total_supply(worker, amm_contract, alice).await?;
+
+    assert_eq!(U128::from(9), shares);
+    assert_eq!(U128::from(9), alice_shares);
+    assert_eq!(U128::from(22), total_shares);
+
+    println!("      Passed ✅ test_add_liquidity_by_provider");
+    Ok(())
+}
+
+async fn test_set_fee(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let res = api::set_fee(worker, amm_contract, alice, 30).await;
+    assert!(res.is_err());
+
+    let res = api::set_fee(worker, amm_contract, owner, 10_000).await;
+    assert!(res.is_err());
+
+    api::set_fee(worker, amm_contract, owner, 30).await?;
+    let fee = api::fee(worker, amm_contract, alice).await?;
+    assert_eq!(30, fee);
+
+    println!("      Passed ✅ test_set_fee");
+    Ok(())
+}
+
+async fn test_swap_with_slippage(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    // Pool holds 43 A and 12 B, 6 A with 0.3% fee are swapped to 2 B
+    let res = api::swap(
+        worker,
+        amm_contract,
+        alice,
         token_a_contract.id(),
+        token_b_contract.id(),
         6.into(),
+        Some(3.into()),
     )
     .await;
+    assert!(res.is_err());
+
+    let balance_a = api::balance_of_token_a(worker, amm_contract, alice).await?;
+    assert_eq!(U128::from(7), balance_a);
+
+    let amount_out = api::swap(
+        worker,
+        amm_contract,
+        alice,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        6.into(),
+        Some(2.into()),
+    )
+    .await?;
+
+    let balance_a = api::balance_of_token_a(worker, amm_contract, alice).await?;
+    let balance_b = api::balance_of_token_b(worker, amm_contract, alice).await?;
+    assert_eq!(U128::from(2), amount_out);
+    assert_eq!(U128::from(1), balance_a);
+    assert_eq!(U128::from(4), balance_b);
 
+    println!("      Passed ✅ test_swap_with_slippage");
+    Ok(())
+}
+
+async fn test_swap_exact_out(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    // Pool holds 49 A and 10 B, exactly 3 A costs 2 B with 0.3% fee
+    let res = api::swap_exact_out(
+        worker,
+        amm_contract,
+        alice,
+        token_b_contract.id(),
+        token_a_contract.id(),
+        3.into(),
+        1.into(),
+    )
+    .await;
     assert!(res.is_err());
 
-    println!("      Passed ✅ test_add_liquidity_with_wrong_tokens");
+    let amount_in = api::swap_exact_out(
+        worker,
+        amm_contract,
+        alice,
+        token_b_contract.id(),
+        token_a_contract.id(),
+        3.into(),
+        2.into(),
+    )
+    .await?;
+
+    let balance_a = api::balance_of_token_a(worker, amm_contract, alice).await?;
+    let balance_b = api::balance_of_token_b(worker, amm_contract, alice).await?;
+    assert_eq!(U128::from(2), amount_in);
+    assert_eq!(U128::from(4), balance_a);
+    assert_eq!(U128::from(2), balance_b);
+
+    println!("      Passed ✅ test_swap_exact_out");
     Ok(())
 }
 
-async fn storage_deposits(
+async fn test_swap_on_transfer(
     worker: &Worker<Sandbox>,
     amm_contract: &Contract,
     token_a_contract: &Contract,
     token_b_contract: &Contract,
     alice: &Account,
+) -> anyhow::Result<()> {
+    // Pool holds 46 A and 12 B, 5 A with 0.3% fee are swapped to 1 B
+    let msg = serde_json::json!({
+        "action": "swap",
+        "to": token_b_contract.id(),
+        "min_out": "2",
+    })
+    .to_string();
+    api::ft_transfer_call_with_msg(
+        worker,
+        token_a_contract,
+        alice,
+        amm_contract.as_account(),
+        5.into(),
+        &msg,
+    )
+    .await?;
+
+    // The whole amount is refunded on a failed swap
+    let balance_a = api::ft_balance_of(worker, alice, token_a_contract.id()).await?;
+    let balance_b = api::ft_balance_of(worker, alice, token_b_contract.id()).await?;
+    assert_eq!(U128::from(25), balance_a);
+    assert_eq!(U128::from(19), balance_b);
+
+    let msg = serde_json::json!({
+        "action": "swap",
+        "to": token_b_contract.id(),
+        "min_out": "1",
+    })
+    .to_string();
+    api::ft_transfer_call_with_msg(
+        worker,
+        token_a_contract,
+        alice,
+        amm_contract.as_account(),
+        5.into(),
+        &msg,
+    )
+    .await?;
+
+    let balance_a = api::ft_balance_of(worker, alice, token_a_contract.id()).await?;
+    let balance_b = api::ft_balance_of(worker, alice, token_b_contract.id()).await?;
+    let internal_balance_b = api::balance_of_token_b(worker, amm_contract, alice).await?;
+    assert_eq!(U128::from(20), balance_a);
+    assert_eq!(U128::from(20), balance_b);
+    assert_eq!(U128::from(2), internal_balance_b);
+
+    println!("      Passed ✅ test_swap_on_transfer");
+    Ok(())
+}
+
+async fn test_add_liquidity_optimal(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        100_000.into(),
+        40_000.into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        11_000.into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_b_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+
+    // A plain top-up should be proportional to the pool
+    let res = api::add_liquidity(
+        worker,
+        owner,
+        &amm_contract,
+        token_a_contract.id(),
+        10_000.into(),
+        token_b_contract.id(),
+        10_000.into(),
+    )
+    .await;
+    assert!(res.is_err());
+
+    // Pool holds 100_000 A and 40_000 B, so only 4_000 B of 10_000 B are deposited with 10_000 A
+    let shares = api::add_liquidity_optimal(
+        worker,
+        owner,
+        &amm_contract,
+        token_a_contract.id(),
+        10_000.into(),
+        token_b_contract.id(),
+        10_000.into(),
+    )
+    .await?;
+    assert_eq!(U128::from(6_324), shares);
+    assert_eq!(
+        U128::from(110_000),
+        api::token_a_in_pool(worker, &amm_contract, owner).await?
+    );
+    assert_eq!(
+        U128::from(44_000),
+        api::token_b_in_pool(worker, &amm_contract, owner).await?
+    );
+    assert_eq!(
+        U128::from(1_000),
+        api::balance_of_token_a(worker, &amm_contract, owner).await?
+    );
+    assert_eq!(
+        U128::from(6_000),
+        api::balance_of_token_b(worker, &amm_contract, owner).await?
+    );
+
+    // 1_000 A and 404 B deviate from proportions of the pool by 0.99%
+    api::set_liquidity_tolerance(worker, &amm_contract, owner, 50).await?;
+    let res = api::add_liquidity(
+        worker,
+        owner,
+        &amm_contract,
+        token_a_contract.id(),
+        1_000.into(),
+        token_b_contract.id(),
+        404.into(),
+    )
+    .await;
+    assert!(res.is_err());
+    api::set_liquidity_tolerance(worker, &amm_contract, owner, 100).await?;
+    api::add_liquidity(
+        worker,
+        owner,
+        &amm_contract,
+        token_a_contract.id(),
+        1_000.into(),
+        token_b_contract.id(),
+        404.into(),
+    )
+    .await?;
+    assert_eq!(
+        U128::from(44_404),
+        api::token_b_in_pool(worker, &amm_contract, owner).await?
+    );
+
+    println!("      Passed ✅ test_add_liquidity_optimal");
+    Ok(())
+}
+
+async fn test_protocol_fees(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        100,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+
+    // A fifth of the swap fee goes to the treasury, that should be registered
+    api::set_protocol_fee(worker, &amm_contract, owner, 2_000).await?;
+    let res = api::set_treasury(worker, &amm_contract, owner, alice.id()).await;
+    assert!(res.is_err());
+    api::storage_deposit(worker, &amm_contract, alice, parse_near!("1 N")).await?;
+    api::set_treasury(worker, &amm_contract, owner, alice.id()).await?;
+
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        100_000.into(),
+    )
+    .await?;
+    api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        100_000.into(),
+        None,
+    )
+    .await?;
+
+    let protocol_fees = api::token_a_protocol_fees(worker, &amm_contract, alice).await?;
+    let amount_token_a = api::token_a_in_pool(worker, &amm_contract, alice).await?;
+    assert_eq!(U128::from(200), protocol_fees);
+    assert_eq!(U128::from(1_099_800), amount_token_a);
+
+    api::claim_protocol_fees(worker, &amm_contract, alice).await?;
+
+    let protocol_fees = api::token_a_protocol_fees(worker, &amm_contract, alice).await?;
+    let treasury_balance = api::balance_of_token_a(worker, &amm_contract, alice).await?;
+    assert_eq!(U128::from(0), protocol_fees);
+    assert_eq!(U128::from(200), treasury_balance);
+
+    // The treasury withdraws claimed fees like any other balance
+    let balance_a = api::ft_balance_of(worker, alice, token_a_contract.id()).await?;
+    api::withdraw_token(
+        worker,
+        &amm_contract,
+        alice,
+        token_a_contract.id(),
+        200.into(),
+    )
+    .await?;
+    assert_eq!(
+        U128::from(balance_a.0 + 200),
+        api::ft_balance_of(worker, alice, token_a_contract.id()).await?
+    );
+    assert_eq!(
+        U128::from(0),
+        api::balance_of_token_a(worker, &amm_contract, alice).await?
+    );
+
+    println!("      Passed ✅ test_protocol_fees");
+    Ok(())
+}
+
+async fn test_pool_with_different_decimals(
+    worker: &Worker<Sandbox>,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    // Token C has 6 decimals and token D has 24 decimals
+    let token_c_wasm = std::fs::read(WASM_FILEPATH_TOKEN_C)?;
+    let token_c_contract = worker.dev_deploy(&token_c_wasm).await?;
+    let token_d_wasm = std::fs::read(WASM_FILEPATH_TOKEN_D)?;
+    let token_d_contract = worker.dev_deploy(&token_d_wasm).await?;
+
+    token_c_contract
+        .call(worker, "new_meta_token")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "total_supply": (1_000_000_000 * 10u128.pow(6)).to_string(),
+        }))?
+        .transact()
+        .await?;
+
+    token_d_contract
+        .call(worker, "new_meta_token")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "total_supply": (1_000_000_000 * 10u128.pow(24)).to_string(),
+        }))?
+        .transact()
+        .await?;
+
+    // 1000 C for 1000 D
+    let amm_contract = create_pool(
+        worker,
+        &token_c_contract,
+        &token_d_contract,
+        owner,
+        0,
+        (1_000 * 10u128.pow(6)).into(),
+        (1_000 * 10u128.pow(24)).into(),
+    )
+    .await?;
+
+    let exchange_rate = api::exchange_rate(worker, &amm_contract, owner).await?;
+    assert_eq!("1", exchange_rate);
+
+    // Top up with the same ratio and keep 1 C for a swap,
+    // raw amounts differ by 18 orders of magnitude
+    api::ft_transfer_call(
+        worker,
+        &token_c_contract,
+        owner,
+        amm_contract.as_account(),
+        (11 * 10u128.pow(6)).into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        &token_d_contract,
+        owner,
+        amm_contract.as_account(),
+        (10 * 10u128.pow(24)).into(),
+    )
+    .await?;
+    let shares = api::add_liquidity(
+        worker,
+        owner,
+        &amm_contract,
+        token_c_contract.id(),
+        (10 * 10u128.pow(6)).into(),
+        token_d_contract.id(),
+        (10 * 10u128.pow(24)).into(),
+    )
+    .await?;
+    assert_eq!(U128::from(10u128.pow(16)), shares);
+
+    // Swap 1 C, pool holds 1010 C and 1010 D
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_c_contract.id(),
+        token_d_contract.id(),
+        10u128.pow(6).into(),
+        None,
+    )
+    .await?;
+    assert_eq!(U128::from(999_010_880_316_518_298_714_145), amount_out);
+
+    let exchange_rate = api::exchange_rate(worker, &amm_contract, owner).await?;
+    assert_eq!("0.99802273899", exchange_rate);
+
+    println!("      Passed ✅ test_pool_with_different_decimals");
+    Ok(())
+}
+
+async fn test_pool_factory(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let factory_wasm = std::fs::read(WASM_FILEPATH_POOL_FACTORY)?;
+    let factory_contract = worker.dev_deploy(&factory_wasm).await?;
+
+    factory_contract
+        .call(worker, "initialize")
+        .args_json(serde_json::json!({ "owner_id": owner.id() }))?
+        .transact()
+        .await?;
+
+    let contract_wasm = std::fs::read(WASM_FILEPATH_CONTRACT)?;
+    api::set_pool_code(worker, &factory_contract, owner, contract_wasm).await?;
+
+    // Only the owner creates pools
+    let res = api::create_pool(
+        worker,
+        &factory_contract,
+        alice,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        30,
+        None,
+    )
+    .await;
+    assert!(res.is_err());
+
+    // Weights follow the order of passed tokens, token A weighs 80%
+    api::create_pool(
+        worker,
+        &factory_contract,
+        owner,
+        token_b_contract.id(),
+        token_a_contract.id(),
+        30,
+        Some(serde_json::json!({"type": "weighted", "weight_a": 2000, "weight_b": 8000})),
+    )
+    .await?;
+
+    // Order of tokens doesn't matter
+    let pool_id = api::get_pool(
+        worker,
+        &factory_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+    )
+    .await?
+    .expect("Pool should be registered");
+    let pools = api::get_pools(worker, &factory_contract, owner, 0, 10).await?;
+    assert_eq!(1, pools.len());
+    assert_eq!(pool_id.to_string(), pools[0]["pool_id"].as_str().unwrap());
+
+    let pools = api::get_pools(worker, &factory_contract, owner, 1, 10).await?;
+    assert!(pools.is_empty());
+
+    // Pair could be created only once, even with another curve
+    let res = api::create_pool(
+        worker,
+        &factory_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        30,
+        None,
+    )
+    .await;
+    assert!(res.is_err());
+
+    // The pool is deployed and initialized
+    let fee: u32 = owner
+        .call(worker, &pool_id, "fee")
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(30, fee);
+    let (weight_a, weight_b) = if token_a_contract.id() < token_b_contract.id() {
+        (8000, 2000)
+    } else {
+        (2000, 8000)
+    };
+    let curve: serde_json::Value = owner
+        .call(worker, &pool_id, "curve")
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(
+        serde_json::json!({"type": "weighted", "weight_a": weight_a, "weight_b": weight_b}),
+        curve
+    );
+
+    println!("      Passed ✅ test_pool_factory");
+    Ok(())
+}
+
+async fn test_route_swap(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    // Token C has 6 decimals
+    let token_c_wasm = std::fs::read(WASM_FILEPATH_TOKEN_C)?;
+    let token_c_contract = worker.dev_deploy(&token_c_wasm).await?;
+
+    token_c_contract
+        .call(worker, "new_meta_token")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "total_supply": (1_000_000_000 * 10u128.pow(6)).to_string(),
+        }))?
+        .transact()
+        .await?;
+
+    let pool_ab = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+    // 0.0001 B for 0.0001 C
+    let pool_bc = create_pool(
+        worker,
+        token_b_contract,
+        &token_c_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        100.into(),
+    )
+    .await?;
+
+    let path = serde_json::json!([
+        {"pool_id": pool_ab.id(), "to": token_b_contract.id()},
+        {"pool_id": pool_bc.id(), "to": token_c_contract.id()},
+    ]);
+    let balance_a = api::ft_balance_of(worker, owner, token_a_contract.id()).await?;
+    let balance_c = api::ft_balance_of(worker, owner, token_c_contract.id()).await?;
+
+    // 100000 A are swapped to 90910 B and then to 8 C, the last hop fails,
+    // so the first one is rolled back
+    let msg = serde_json::json!({"action": "route", "path": path, "min_out": "9"}).to_string();
+    api::ft_transfer_call_with_msg(
+        worker,
+        token_a_contract,
+        owner,
+        pool_ab.as_account(),
+        100_000.into(),
+        &msg,
+    )
+    .await?;
+
+    let amount_token_a = api::token_a_in_pool(worker, &pool_ab, owner).await?;
+    let amount_token_b = api::token_b_in_pool(worker, &pool_ab, owner).await?;
+    assert_eq!(U128::from(1_000_000), amount_token_a);
+    assert_eq!(U128::from(1_000_000), amount_token_b);
+    assert_eq!(
+        balance_a,
+        api::ft_balance_of(worker, owner, token_a_contract.id()).await?
+    );
+
+    let msg = serde_json::json!({"action": "route", "path": path, "min_out": "8"}).to_string();
+    api::ft_transfer_call_with_msg(
+        worker,
+        token_a_contract,
+        owner,
+        pool_ab.as_account(),
+        100_000.into(),
+        &msg,
+    )
+    .await?;
+
+    let amount_token_a = api::token_a_in_pool(worker, &pool_ab, owner).await?;
+    let amount_token_b = api::token_b_in_pool(worker, &pool_ab, owner).await?;
+    assert_eq!(U128::from(1_100_000), amount_token_a);
+    assert_eq!(U128::from(909_090), amount_token_b);
+    assert_eq!(
+        U128::from(balance_a.0 - 100_000),
+        api::ft_balance_of(worker, owner, token_a_contract.id()).await?
+    );
+    assert_eq!(
+        U128::from(balance_c.0 + 8),
+        api::ft_balance_of(worker, owner, token_c_contract.id()).await?
+    );
+
+    println!("      Passed ✅ test_route_swap");
+    Ok(())
+}
+
+async fn test_stable_swap_pool(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool_with_curve(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        serde_json::json!({"type": "stable_swap", "amp": 100}),
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+
+    // Only the owner changes the amplification
+    let res = api::set_amp(worker, &amm_contract, alice, 200).await;
+    assert!(res.is_err());
+    let res = api::set_amp(worker, &amm_contract, owner, 0).await;
+    assert!(res.is_err());
+
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+    // The constant product would give only 9901 B
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+        None,
+    )
+    .await?;
+    assert_eq!(U128::from(9_999), amount_out);
+
+    api::set_amp(worker, &amm_contract, owner, 200).await?;
+    let curve = api::curve(worker, &amm_contract, owner).await?;
+    assert_eq!(
+        serde_json::json!({"type": "stable_swap", "amp": 200}),
+        curve
+    );
+
+    println!("      Passed ✅ test_stable_swap_pool");
+    Ok(())
+}
+
+async fn test_weighted_pool(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    // 80/20 pool with equal balances prices A four times higher than B
+    let amm_contract = create_pool_with_curve(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        serde_json::json!({"type": "weighted", "weight_a": 8_000, "weight_b": 2_000}),
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+
+    let shares = api::ft_balance_of(worker, owner, amm_contract.id()).await?;
+    assert_eq!(U128::from(100 * 10u128.pow(18)), shares);
+    let exchange_rate = api::exchange_rate(worker, &amm_contract, owner).await?;
+    assert_eq!("4", exchange_rate);
+
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        100_000.into(),
+    )
+    .await?;
+    // 1 - (1 / 1.1)^4 of the pool balance B
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        100_000.into(),
+        None,
+    )
+    .await?;
+    assert_eq!(U128::from(316_986), amount_out);
+
+    println!("      Passed ✅ test_weighted_pool");
+    Ok(())
+}
+
+async fn test_concentrated_liquidity(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = deploy_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        30,
+        serde_json::json!({"type": "concentrated", "tick_spacing": 10, "initial_tick": 0}),
+    )
+    .await?;
+
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        1_010_000.into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_b_contract,
+        owner,
+        amm_contract.as_account(),
+        1_000_000.into(),
+    )
+    .await?;
+
+    // The whole liquidity is in a range of prices 0.99..1.01
+    let position_id = api::open_position(
+        worker,
+        &amm_contract,
+        owner,
+        -100,
+        100,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+    assert_eq!(0, position_id);
+    let token_a_in_pool = api::token_a_in_pool(worker, &amm_contract, owner).await?;
+    assert_eq!(U128::from(1_000_000), token_a_in_pool);
+
+    // A constant product pool of the same amounts would return 9_871
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+        None,
+    )
+    .await?;
+    assert_eq!(U128::from(9_969), amount_out);
+    let current_tick = api::current_tick(worker, &amm_contract, owner).await?;
+    assert_eq!(-1, current_tick);
+
+    let position = api::get_position(worker, &amm_contract, owner, position_id).await?;
+    assert_eq!(serde_json::json!("29"), position["fees_a"]);
+
+    let amounts = api::close_position(worker, &amm_contract, owner, position_id).await?;
+    assert_eq!((U128::from(1_009_998), U128::from(990_030)), amounts);
+    let position = api::get_position(worker, &amm_contract, owner, position_id).await?;
+    assert!(position.is_null());
+
+    println!("      Passed ✅ test_concentrated_liquidity");
+    Ok(())
+}
+
+async fn test_price_oracle(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        2_000_000.into(),
+    )
+    .await?;
+
+    // Let the price be held for a few blocks
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+    let price_a = api::consult(worker, &amm_contract, owner, token_a_contract.id(), 1).await?;
+    assert_eq!("2", price_a);
+    let price_b = api::consult(worker, &amm_contract, owner, token_b_contract.id(), 1).await?;
+    assert_eq!("0.5", price_b);
+
+    println!("      Passed ✅ test_price_oracle");
+    Ok(())
+}
+
+async fn test_quotes(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        30,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        20_000.into(),
+    )
+    .await?;
+
+    let quote = api::get_amount_out(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+    )
+    .await?;
+    assert_eq!(serde_json::json!("9872"), quote["amount_out"]);
+    assert_eq!(serde_json::json!("30"), quote["fee"]);
+    assert_eq!(serde_json::json!("1"), quote["spot_price_before"]);
+    assert_eq!(serde_json::json!(196), quote["price_impact"]);
+
+    // The quote matches the swap
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+        None,
+    )
+    .await?;
+    assert_eq!(serde_json::json!(amount_out), quote["amount_out"]);
+    let exchange_rate = api::exchange_rate(worker, &amm_contract, owner).await?;
+    assert_eq!(serde_json::json!(exchange_rate), quote["spot_price_after"]);
+
+    let quote = api::get_amount_in(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        5_000.into(),
+    )
+    .await?;
+    let amount_in = api::swap_exact_out(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        5_000.into(),
+        10_000.into(),
+    )
+    .await?;
+    assert_eq!(serde_json::json!(amount_in), quote["amount_in"]);
+
+    println!("      Passed ✅ test_quotes");
+    Ok(())
+}
+
+async fn test_pool_info(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        30,
+        1_000_000.into(),
+        2_000_000.into(),
+    )
+    .await?;
+    let total_shares = api::ft_total_supply(worker, &amm_contract, owner).await?;
+
+    let pool_info = api::pool_info(worker, &amm_contract, owner).await?;
+    assert_eq!(
+        serde_json::json!({
+            "token_a": {
+                "id": token_a_contract.id(),
+                "symbol": "A$",
+                "decimals": 10,
+                "reserve": "1000000",
+            },
+            "token_b": {
+                "id": token_b_contract.id(),
+                "symbol": "B$",
+                "decimals": 10,
+                "reserve": "2000000",
+            },
+            "exchange_rate": "2",
+            "fee": 30,
+            "protocol_fee": 0,
+            "treasury_id": owner.id(),
+            "total_shares": total_shares,
+            "owner_id": owner.id(),
+        }),
+        pool_info
+    );
+
+    println!("      Passed ✅ test_pool_info");
+    Ok(())
+}
+
+async fn test_storage_management(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+    let balance_a = api::ft_balance_of(worker, alice, token_a_contract.id()).await?;
+
+    // Transfers of an unregistered account are refunded
+    let storage_balance = api::storage_balance_of(worker, &amm_contract, alice).await?;
+    assert!(storage_balance.is_null());
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        alice,
+        amm_contract.as_account(),
+        10.into(),
+    )
+    .await?;
+    assert_eq!(
+        balance_a,
+        api::ft_balance_of(worker, alice, token_a_contract.id()).await?
+    );
+    let res = api::swap(
+        worker,
+        &amm_contract,
+        alice,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10.into(),
+        None,
+    )
+    .await;
+    assert!(res.is_err());
+
+    api::storage_deposit(worker, &amm_contract, alice, parse_near!("1 N")).await?;
+    let storage_balance = api::storage_balance_of(worker, &amm_contract, alice).await?;
+    assert_eq!(serde_json::json!("0"), storage_balance["available"]);
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        alice,
+        amm_contract.as_account(),
+        10.into(),
+    )
+    .await?;
+    let internal_balance_a = api::balance_of_token_a(worker, &amm_contract, alice).await?;
+    assert_eq!(U128::from(10), internal_balance_a);
+
+    // Balances should be withdrawn before the account is unregistered
+    let res = api::storage_unregister(worker, &amm_contract, alice, false).await;
+    assert!(res.is_err());
+    api::withdraw_token(
+        worker,
+        &amm_contract,
+        alice,
+        token_a_contract.id(),
+        10.into(),
+    )
+    .await?;
+    assert!(api::storage_unregister(worker, &amm_contract, alice, false).await?);
+    let storage_balance = api::storage_balance_of(worker, &amm_contract, alice).await?;
+    assert!(storage_balance.is_null());
+
+    println!("      Passed ✅ test_storage_management");
+    Ok(())
+}
+
+async fn test_ownership_transfer(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = deploy_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        serde_json::json!({"type": "constant_product"}),
+    )
+    .await?;
+
+    // Only the owner proposes, and only the proposed account accepts
+    let res = api::propose_owner(worker, &amm_contract, alice, alice.id()).await;
+    assert!(res.is_err());
+    api::propose_owner(worker, &amm_contract, owner, alice.id()).await?;
+    let res = api::accept_owner(worker, &amm_contract, owner).await;
+    assert!(res.is_err());
+    assert_eq!(
+        owner.id(),
+        &api::get_owner(worker, &amm_contract, owner).await?
+    );
+
+    api::accept_owner(worker, &amm_contract, alice).await?;
+    assert_eq!(
+        alice.id(),
+        &api::get_owner(worker, &amm_contract, owner).await?
+    );
+    let res = api::set_fee(worker, &amm_contract, owner, 30).await;
+    assert!(res.is_err());
+    api::set_fee(worker, &amm_contract, alice, 30).await?;
+
+    println!("      Passed ✅ test_ownership_transfer");
+    Ok(())
+}
+
+async fn test_pause(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+
+    // Only the owner pauses operations
+    let res = api::set_paused(worker, &amm_contract, alice, "deposit", true).await;
+    assert!(res.is_err());
+
+    // Transfers are refunded while deposits are paused
+    api::set_paused(worker, &amm_contract, owner, "deposit", true).await?;
+    let balance_a = api::ft_balance_of(worker, owner, token_a_contract.id()).await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+    assert_eq!(
+        balance_a,
+        api::ft_balance_of(worker, owner, token_a_contract.id()).await?
+    );
+    api::set_paused(worker, &amm_contract, owner, "deposit", false).await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+
+    // The withdraw-only mode freezes everything except withdrawals
+    api::set_paused(worker, &amm_contract, owner, "withdraw", true).await?;
+    api::set_withdraw_only(worker, &amm_contract, owner, true).await?;
+    let res = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+        None,
+    )
+    .await;
+    assert!(res.is_err());
+    api::withdraw_token(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        10_000.into(),
+    )
+    .await?;
+    let internal_balance_a = api::balance_of_token_a(worker, &amm_contract, owner).await?;
+    assert_eq!(U128::from(0), internal_balance_a);
+
+    println!("      Passed ✅ test_pause");
+    Ok(())
+}
+
+async fn test_upgrade(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        30,
+        1_000_000.into(),
+        2_000_000.into(),
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+    let pool_info = api::pool_info(worker, &amm_contract, owner).await?;
+    let shares = api::ft_balance_of(worker, owner, amm_contract.id()).await?;
+
+    // The pool is moved to the previous layout, that doesn't have pending transfers at the end
+    // of the state, so the upgrade converts the stored layout. Their map is stored as
+    // its one-byte prefix with the length.
+    let state_version = api::state_version(worker, &amm_contract, owner).await?;
+    let state = api::contract_state(worker, &amm_contract).await?;
+    let previous_state = &state[..state.len() - std::mem::size_of::<u32>() - 1];
+    api::patch_pool_state(worker, &amm_contract, previous_state, state_version - 1).await?;
+    assert_eq!(
+        state_version - 1,
+        api::state_version(worker, &amm_contract, owner).await?
+    );
+
+    // Only the owner upgrades the pool
+    let contract_wasm = std::fs::read(WASM_FILEPATH_CONTRACT)?;
+    let res = api::upgrade(worker, &amm_contract, alice, contract_wasm.clone()).await;
+    assert!(res.is_err());
+
+    api::upgrade(worker, &amm_contract, owner, contract_wasm).await?;
+    assert_eq!(
+        state_version,
+        api::state_version(worker, &amm_contract, owner).await?
+    );
+    assert_eq!(
+        100,
+        api::liquidity_tolerance(worker, &amm_contract, owner).await?
+    );
+
+    // The live state is kept and the pool keeps working
+    assert_eq!(
+        pool_info,
+        api::pool_info(worker, &amm_contract, owner).await?
+    );
+    assert_eq!(
+        shares,
+        api::ft_balance_of(worker, owner, amm_contract.id()).await?
+    );
+    let amount_out = api::swap(
+        worker,
+        &amm_contract,
+        owner,
+        token_a_contract.id(),
+        token_b_contract.id(),
+        10_000.into(),
+        None,
+    )
+    .await?;
+    assert!(amount_out.0 > 0);
+
+    println!("      Passed ✅ test_upgrade");
+    Ok(())
+}
+
+async fn test_roles(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = deploy_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        serde_json::json!({"type": "constant_product"}),
+    )
+    .await?;
+
+    // Only admins grant roles
+    let res = api::grant_role(worker, &amm_contract, alice, "fee_manager", alice.id()).await;
+    assert!(res.is_err());
+    api::grant_role(worker, &amm_contract, owner, "fee_manager", alice.id()).await?;
+    let fee_managers = api::get_role_members(worker, &amm_contract, owner, "fee_manager").await?;
+    assert_eq!(vec![alice.id().clone()], fee_managers);
+
+    // A fee manager changes fees, but doesn't pause the pool
+    api::set_fee(worker, &amm_contract, alice, 30).await?;
+    let res = api::set_paused(worker, &amm_contract, alice, "swap", true).await;
+    assert!(res.is_err());
+
+    api::revoke_role(worker, &amm_contract, owner, "fee_manager", alice.id()).await?;
+    let fee_managers = api::get_role_members(worker, &amm_contract, owner, "fee_manager").await?;
+    assert!(fee_managers.is_empty());
+    let res = api::set_fee(worker, &amm_contract, alice, 20).await;
+    assert!(res.is_err());
+    assert_eq!(30, api::fee(worker, &amm_contract, owner).await?);
+
+    println!("      Passed ✅ test_roles");
+    Ok(())
+}
+
+async fn test_operators(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
+) -> anyhow::Result<()> {
+    let amm_contract = create_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        0,
+        1_000_000.into(),
+        1_000_000.into(),
+    )
+    .await?;
+    let proxy_wasm = std::fs::read(WASM_FILEPATH_PROXY)?;
+    let proxy_contract = worker.dev_deploy(&proxy_wasm).await?;
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        10_000.into(),
+    )
+    .await?;
+    let balance_a = api::balance_of_token_a(worker, &amm_contract, owner).await?;
+
+    // A contract called by the owner acts on its own behalf, not on behalf of the owner
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "add_liquidity",
+        serde_json::json!({
+            "token_a_id": token_a_contract.id(),
+            "amount_liq_a": U128::from(10_000),
+            "token_b_id": token_b_contract.id(),
+            "amount_liq_b": U128::from(10_000),
+        }),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(
+        balance_a,
+        api::balance_of_token_a(worker, &amm_contract, owner).await?
+    );
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "set_fee",
+        serde_json::json!({ "fee": 30 }),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(0, api::fee(worker, &amm_contract, owner).await?);
+
+    // Only the owner approves operators, an operator holds every role except the admin
+    let res = api::approve_operator(worker, &amm_contract, alice, proxy_contract.id()).await;
+    assert!(res.is_err());
+    api::approve_operator(worker, &amm_contract, owner, proxy_contract.id()).await?;
+    assert_eq!(
+        vec![proxy_contract.id().clone()],
+        api::get_operators(worker, &amm_contract, owner).await?
+    );
+    api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "set_fee",
+        serde_json::json!({ "fee": 30 }),
+    )
+    .await?;
+    assert_eq!(30, api::fee(worker, &amm_contract, owner).await?);
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "grant_role",
+        serde_json::json!({ "role": "admin", "account_id": proxy_contract.id() }),
+    )
+    .await;
+    assert!(res.is_err());
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "set_treasury",
+        serde_json::json!({ "treasury_id": proxy_contract.id() }),
+    )
+    .await;
+    assert!(res.is_err());
+
+    // A revoked operator keeps no access
+    api::revoke_operator(worker, &amm_contract, owner, proxy_contract.id()).await?;
+    assert!(api::get_operators(worker, &amm_contract, owner)
+        .await?
+        .is_empty());
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "set_fee",
+        serde_json::json!({ "fee": 20 }),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(30, api::fee(worker, &amm_contract, owner).await?);
+    let res = api::proxy_call(
+        worker,
+        &proxy_contract,
+        owner,
+        &amm_contract,
+        "grant_role",
+        serde_json::json!({ "role": "fee_manager", "account_id": proxy_contract.id() }),
+    )
+    .await;
+    assert!(res.is_err());
+    assert!(api::get_role_members(worker, &amm_contract, owner, "admin")
+        .await?
+        .is_empty());
+    assert!(
+        api::get_role_members(worker, &amm_contract, owner, "fee_manager")
+            .await?
+            .is_empty()
+    );
+
+    println!("      Passed ✅ test_operators");
+    Ok(())
+}
+
+/// Deploys and initializes a new pool, the owner provides an initial liquidity
+async fn create_pool(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    fee: u32,
+    amount_liq_a: U128,
+    amount_liq_b: U128,
+) -> anyhow::Result<Contract> {
+    create_pool_with_curve(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        fee,
+        serde_json::json!({"type": "constant_product"}),
+        amount_liq_a,
+        amount_liq_b,
+    )
+    .await
+}
+
+/// Same as [create_pool], but the pool pricing function is passed as a `curve`
+#[allow(clippy::too_many_arguments)]
+async fn create_pool_with_curve(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    fee: u32,
+    curve: serde_json::Value,
+    amount_liq_a: U128,
+    amount_liq_b: U128,
+) -> anyhow::Result<Contract> {
+    let amm_contract = deploy_pool(
+        worker,
+        token_a_contract,
+        token_b_contract,
+        owner,
+        fee,
+        curve,
+    )
+    .await?;
+
+    api::ft_transfer_call(
+        worker,
+        token_a_contract,
+        owner,
+        amm_contract.as_account(),
+        amount_liq_a,
+    )
+    .await?;
+    api::ft_transfer_call(
+        worker,
+        token_b_contract,
+        owner,
+        amm_contract.as_account(),
+        amount_liq_b,
+    )
+    .await?;
+    api::add_liquidity(
+        worker,
+        owner,
+        &amm_contract,
+        token_a_contract.id(),
+        amount_liq_a,
+        token_b_contract.id(),
+        amount_liq_b,
+    )
+    .await?;
+
+    Ok(amm_contract)
+}
+
+/// Deploys and initializes a new pool without a liquidity
+async fn deploy_pool(
+    worker: &Worker<Sandbox>,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    fee: u32,
+    curve: serde_json::Value,
+) -> anyhow::Result<Contract> {
+    let contract_wasm = std::fs::read(WASM_FILEPATH_CONTRACT)?;
+    let amm_contract = worker.dev_deploy(&contract_wasm).await?;
+
+    amm_contract
+        .call(worker, "initialize")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "token_a_id": token_a_contract.id(),
+            "token_b_id": token_b_contract.id(),
+            "fee": fee,
+            "curve": curve,
+        }))?
+        .max_gas()
+        .transact()
+        .await?;
+
+    api::storage_deposit(
+        worker,
+        token_a_contract,
+        amm_contract.as_account(),
+        parse_near!("1 N"),
+    )
+    .await?;
+    api::storage_deposit(
+        worker,
+        token_b_contract,
+        amm_contract.as_account(),
+        parse_near!("1 N"),
+    )
+    .await?;
+    api::storage_deposit(worker, &amm_contract, owner, parse_near!("1 N")).await?;
+
+    Ok(amm_contract)
+}
+
+async fn storage_deposits(
+    worker: &Worker<Sandbox>,
+    amm_contract: &Contract,
+    token_a_contract: &Contract,
+    token_b_contract: &Contract,
+    owner: &Account,
+    alice: &Account,
 ) -> anyhow::Result<()> {
     api::storage_deposit(
         worker,
@@ -445,6 +2187,8 @@ async fn storage_deposits(
 
     api::storage_deposit(worker, token_a_contract, alice, parse_near!("1 N")).await?;
     api::storage_deposit(worker, token_b_contract, alice, parse_near!("1 N")).await?;
+    api::storage_deposit(worker, amm_contract, owner, parse_near!("1 N")).await?;
+    api::storage_deposit(worker, amm_contract, alice, parse_near!("1 N")).await?;
 
     Ok(())
 }
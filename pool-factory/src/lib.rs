@@ -0,0 +1,432 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LazyOption, UnorderedMap},
+    json_types::U128,
+    serde_json, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise, PromiseResult,
+};
+use near_sdk::{env, ext_contract, log, near_bindgen};
+use serde::{Deserialize, Serialize};
+
+/// Deposit that covers an account creation and a storage of the pool code
+const POOL_DEPOSIT: Balance = 5_000_000_000_000_000_000_000_000;
+/// Deposit for a registration of the pool in a token contract, the excess is refunded
+const TOKEN_STORAGE_DEPOSIT: Balance = 12_500_000_000_000_000_000_000;
+const GAS_FOR_POOL_INIT: Gas = Gas(50_000_000_000_000);
+const GAS_FOR_STORAGE_DEPOSIT: Gas = Gas(10_000_000_000_000);
+/// Number of hash bytes that are used in a pool account name
+const POOL_NAME_HASH_LEN: usize = 6;
+
+#[ext_contract(ext_self)]
+pub trait ExtSelf {
+    fn on_pool_created(
+        &mut self,
+        token_a_id: AccountId,
+        token_b_id: AccountId,
+        pool_id: AccountId,
+        creator_id: AccountId,
+        deposit: U128,
+    ) -> bool;
+}
+
+#[ext_contract(ext_storage)]
+pub trait StorageManagement {
+    fn storage_deposit(account_id: AccountId, registration_only: bool);
+}
+
+#[derive(BorshStorageKey, BorshSerialize)]
+enum StorageKey {
+    PoolCode,
+    Pools,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct PoolFactory {
+    owner_id: AccountId,
+    /// Wasm code of the `amm-contract` that is deployed for every pool
+    pool_code: LazyOption<Vec<u8>>,
+    /// Registry of pools, a pair of sorted token ids maps to the pool account.
+    /// A pair has a single pool, whatever its curve is.
+    pools: UnorderedMap<(AccountId, AccountId), AccountId>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolView {
+    pub token_a_id: AccountId,
+    pub token_b_id: AccountId,
+    pub pool_id: AccountId,
+}
+
+/// Pricing function of a pool, it mirrors the `Curve` of `amm-contract`
+/// and is passed to the pool `initialize`
+///
+/// # Examples
+/// ```json
+/// {"type":"stable_swap","amp":100}
+/// {"type":"weighted","weight_a":8000,"weight_b":2000}
+/// ```
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", tag = "type", rename_all = "snake_case")]
+pub enum Curve {
+    ConstantProduct,
+    StableSwap {
+        amp: u64,
+    },
+    Weighted {
+        weight_a: u32,
+        weight_b: u32,
+    },
+    Concentrated {
+        tick_spacing: u32,
+        initial_tick: i32,
+    },
+}
+
+impl Curve {
+    /// The same curve for tokens in the reversed order
+    fn reversed(self) -> Self {
+        match self {
+            Curve::Weighted { weight_a, weight_b } => Curve::Weighted {
+                weight_a: weight_b,
+                weight_b: weight_a,
+            },
+            Curve::Concentrated {
+                tick_spacing,
+                initial_tick,
+            } => Curve::Concentrated {
+                tick_spacing,
+                initial_tick: -initial_tick,
+            },
+            curve => curve,
+        }
+    }
+}
+
+#[near_bindgen]
+impl PoolFactory {
+    #[init]
+    pub fn initialize(owner_id: AccountId) -> Self {
+        Self {
+            owner_id,
+            pool_code: LazyOption::new(StorageKey::PoolCode, None),
+            pools: UnorderedMap::new(StorageKey::Pools),
+        }
+    }
+
+    /// Stores the `amm-contract` wasm, that is passed as a raw input
+    pub fn set_pool_code(&mut self) {
+        self.assert_owner();
+        let code = env::input().unwrap_or_else(|| env::panic_str("Pool code is empty"));
+        self.pool_code.set(&code);
+    }
+
+    /// Deploys a new pool for a pair of tokens to a deterministic subaccount
+    /// and initializes it, the attached deposit should cover a pool storage.
+    /// The `curve` is the constant product by default, its parameters follow the order
+    /// of passed tokens, they are reversed with tokens if the pool sorts them.
+    #[payable]
+    pub fn create_pool(
+        &mut self,
+        token_a_id: AccountId,
+        token_b_id: AccountId,
+        fee: u32,
+        curve: Option<Curve>,
+    ) -> Promise {
+        self.assert_owner();
+
+        let deposit = env::attached_deposit();
+        if deposit < POOL_DEPOSIT {
+            env::panic_str(
+                format!("Attached deposit {deposit} is less than required {POOL_DEPOSIT}").as_str(),
+            );
+        }
+
+        let curve = match curve {
+            Some(curve) if token_a_id > token_b_id => Some(curve.reversed()),
+            curve => curve,
+        };
+        let (token_a_id, token_b_id) = sorted_pair(token_a_id, token_b_id);
+        if let Some(pool_id) = self.pools.get(&(token_a_id.clone(), token_b_id.clone())) {
+            env::panic_str(format!("Pool for this pair already exists {pool_id}").as_str());
+        }
+
+        let code = self
+            .pool_code
+            .get()
+            .unwrap_or_else(|| env::panic_str("Pool code isn't set"));
+        let pool_id = pool_account_id(&token_a_id, &token_b_id);
+        let args = serde_json::to_vec(&serde_json::json!({
+            "owner_id": self.owner_id,
+            "token_a_id": token_a_id,
+            "token_b_id": token_b_id,
+            "fee": fee,
+            "curve": curve,
+        }))
+        .unwrap();
+
+        // The pool should be registered in both token contracts to receive tokens
+        for token_id in [&token_a_id, &token_b_id] {
+            ext_storage::ext(token_id.clone())
+                .with_attached_deposit(TOKEN_STORAGE_DEPOSIT)
+                .with_static_gas(GAS_FOR_STORAGE_DEPOSIT)
+                .storage_deposit(pool_id.clone(), true);
+        }
+
+        Promise::new(pool_id.clone())
+            .create_account()
+            .transfer(deposit - 2 * TOKEN_STORAGE_DEPOSIT)
+            .deploy_contract(code)
+            .function_call("initialize".to_string(), args, 0, GAS_FOR_POOL_INIT)
+            .then(ext_self::ext(env::current_account_id()).on_pool_created(
+                token_a_id,
+                token_b_id,
+                pool_id,
+                env::predecessor_account_id(),
+                deposit.into(),
+            ))
+    }
+
+    /// Returns the pool account of a pair, order of tokens doesn't matter
+    pub fn get_pool(&self, token_a_id: AccountId, token_b_id: AccountId) -> Option<AccountId> {
+        self.pools.get(&sorted_pair(token_a_id, token_b_id))
+    }
+
+    pub fn get_number_of_pools(&self) -> u64 {
+        self.pools.len()
+    }
+
+    /// Returns `limit` pools starting from `from_index`
+    pub fn get_pools(&self, from_index: u64, limit: u64) -> Vec<PoolView> {
+        let keys = self.pools.keys_as_vector();
+        let values = self.pools.values_as_vector();
+
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| {
+                let (token_a_id, token_b_id) = keys.get(index).unwrap();
+                PoolView {
+                    token_a_id,
+                    token_b_id,
+                    pool_id: values.get(index).unwrap(),
+                }
+            })
+            .collect()
+    }
+
+    #[private]
+    pub fn on_pool_created(
+        &mut self,
+        token_a_id: AccountId,
+        token_b_id: AccountId,
+        pool_id: AccountId,
+        creator_id: AccountId,
+        deposit: U128,
+    ) -> bool {
+        assert_eq!(env::promise_results_count(), 1, "Expected 1 promise result");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::panic_str("Pool creation promise isn't ready"),
+            PromiseResult::Successful(_) => {
+                self.pools.insert(&(token_a_id, token_b_id), &pool_id);
+                true
+            }
+            PromiseResult::Failed => {
+                // Deposit for the pool account is returned to the factory on a failure
+                log!(
+                    "Couldn't create a pool {pool_id}, refund {} to {creator_id}",
+                    deposit.0
+                );
+                Promise::new(creator_id).transfer(deposit.0 - 2 * TOKEN_STORAGE_DEPOSIT);
+                false
+            }
+        }
+    }
+
+    fn assert_owner(&self) {
+        if self.owner_id != env::predecessor_account_id() {
+            env::panic_str("Access unauthorized");
+        }
+    }
+}
+
+fn sorted_pair(token_a_id: AccountId, token_b_id: AccountId) -> (AccountId, AccountId) {
+    if token_a_id == token_b_id {
+        env::panic_str("Pool requires two different tokens");
+    }
+
+    if token_a_id < token_b_id {
+        (token_a_id, token_b_id)
+    } else {
+        (token_b_id, token_a_id)
+    }
+}
+
+/// Subaccount of the factory, its name is derived from a hash of the pair
+fn pool_account_id(token_a_id: &AccountId, token_b_id: &AccountId) -> AccountId {
+    let hash = env::sha256(format!("{token_a_id}:{token_b_id}").as_bytes());
+    let name: String = hash[..POOL_NAME_HASH_LEN]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!("pool-{name}.{}", env::current_account_id())
+        .parse()
+        .unwrap_or_else(|_| env::panic_str("Couldn't create a pool account id"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env, RuntimeFeesConfig, VMConfig};
+
+    fn context() -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id("factory.near".parse().unwrap());
+        builder
+    }
+
+    /// Context of a callback that receives a `result` of the pool creation
+    fn set_callback_context(result: PromiseResult) {
+        testing_env!(
+            context().build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
+    fn factory() -> PoolFactory {
+        testing_env!(context().build());
+        PoolFactory::initialize("owner.near".parse().unwrap())
+    }
+
+    fn account(id: &str) -> AccountId {
+        id.parse().unwrap()
+    }
+
+    /// Registers a pool of a pair like a successful creation does
+    fn add_pool(factory: &mut PoolFactory, token_a_id: &str, token_b_id: &str) -> AccountId {
+        let pool_id = pool_account_id(&account(token_a_id), &account(token_b_id));
+        set_callback_context(PromiseResult::Successful(vec![]));
+        assert!(factory.on_pool_created(
+            account(token_a_id),
+            account(token_b_id),
+            pool_id.clone(),
+            account("owner.near"),
+            POOL_DEPOSIT.into(),
+        ));
+        pool_id
+    }
+
+    #[test]
+    fn test_sorted_pair_ignores_order() {
+        let pair = (account("token-a.near"), account("token-b.near"));
+        assert_eq!(
+            pair,
+            sorted_pair(account("token-a.near"), account("token-b.near"))
+        );
+        assert_eq!(
+            pair,
+            sorted_pair(account("token-b.near"), account("token-a.near"))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool requires two different tokens")]
+    fn test_sorted_pair_of_same_token() {
+        sorted_pair(account("token-a.near"), account("token-a.near"));
+    }
+
+    #[test]
+    fn test_pool_account_id() {
+        testing_env!(context().build());
+        let pool_id = pool_account_id(&account("token-a.near"), &account("token-b.near"));
+        assert_eq!(
+            pool_id,
+            pool_account_id(&account("token-a.near"), &account("token-b.near"))
+        );
+        assert_ne!(
+            pool_id,
+            pool_account_id(&account("token-a.near"), &account("token-c.near"))
+        );
+
+        // A valid subaccount of the factory
+        let name = pool_id.as_str().strip_suffix(".factory.near").unwrap();
+        assert_eq!("pool-".len() + 2 * POOL_NAME_HASH_LEN, name.len());
+        assert_eq!(pool_id, pool_id.as_str().parse::<AccountId>().unwrap());
+    }
+
+    #[test]
+    fn test_get_pools_past_the_end() {
+        let mut factory = factory();
+        let pool_ab = add_pool(&mut factory, "token-a.near", "token-b.near");
+        let pool_bc = add_pool(&mut factory, "token-b.near", "token-c.near");
+        assert_eq!(2, factory.get_number_of_pools());
+
+        let pools = factory.get_pools(1, 10);
+        assert_eq!(1, pools.len());
+        assert_eq!(pool_bc, pools[0].pool_id);
+        assert_eq!(pool_ab, factory.get_pools(0, 1)[0].pool_id);
+        assert!(factory.get_pools(2, 10).is_empty());
+        assert!(factory.get_pools(10, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_failed_pool_creation_is_refunded() {
+        let mut factory = factory();
+        let pool_id = pool_account_id(&account("token-a.near"), &account("token-b.near"));
+
+        set_callback_context(PromiseResult::Failed);
+        assert!(!factory.on_pool_created(
+            account("token-a.near"),
+            account("token-b.near"),
+            pool_id,
+            account("owner.near"),
+            POOL_DEPOSIT.into(),
+        ));
+        assert_eq!(0, factory.get_number_of_pools());
+
+        // Only the deposit of the pool account is refunded, token storage deposits are spent
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(1, receipts.len());
+        assert_eq!(account("owner.near"), receipts[0].receiver_id);
+        assert_eq!(
+            vec![VmAction::Transfer {
+                deposit: POOL_DEPOSIT - 2 * TOKEN_STORAGE_DEPOSIT
+            }],
+            receipts[0].actions
+        );
+    }
+
+    #[test]
+    fn test_reversed_curve() {
+        let curve: Curve =
+            serde_json::from_str(r#"{"type":"weighted","weight_a":8000,"weight_b":2000}"#).unwrap();
+        assert_eq!(
+            Curve::Weighted {
+                weight_a: 2000,
+                weight_b: 8000
+            },
+            curve.reversed()
+        );
+
+        let curve = Curve::Concentrated {
+            tick_spacing: 10,
+            initial_tick: 100,
+        };
+        assert_eq!(
+            Curve::Concentrated {
+                tick_spacing: 10,
+                initial_tick: -100
+            },
+            curve.reversed()
+        );
+        assert_eq!(
+            Curve::StableSwap { amp: 100 },
+            Curve::StableSwap { amp: 100 }.reversed()
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! Test contract that forwards calls of any account to other contracts,
+//! so the pool sees the proxy as a caller while the signer is the original account.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId, Gas, Promise};
+
+const GAS_FOR_CALL: Gas = Gas(50_000_000_000_000);
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Proxy {}
+
+#[near_bindgen]
+impl Proxy {
+    /// Calls a `method_name` of a `contract_id` with JSON `args` and an attached deposit
+    #[payable]
+    pub fn call(&mut self, contract_id: AccountId, method_name: String, args: String) -> Promise {
+        Promise::new(contract_id).function_call(
+            method_name,
+            args.into_bytes(),
+            env::attached_deposit(),
+            GAS_FOR_CALL,
+        )
+    }
+}
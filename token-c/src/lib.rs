@@ -0,0 +1,79 @@
+use near_contract_standards::fungible_token::{
+    events::FtMint,
+    metadata::{FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC},
+    FungibleToken,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    collections::LazyOption, env, json_types::U128, log, near_bindgen, AccountId, Balance,
+    PanicOnDefault, PromiseOrValue,
+};
+
+const DATA_IMAGE_SVG_TOKEN_C: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' width='140' height='140'%3E%3Cg fill='none' stroke='%23000' stroke-width='4'%3E%3Ccircle cx='70' cy='70' r='68'/%3E%3Ccircle cx='70' cy='70' r='54'/%3E%3C/g%3E%3Ctext x='70' y='92' font-family='sans-serif' font-size='64' text-anchor='middle'%3EC%3C/text%3E%3C/svg%3E";
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct TokenC {
+    token: FungibleToken,
+    metadata: LazyOption<FungibleTokenMetadata>,
+}
+
+#[near_bindgen]
+impl TokenC {
+    #[init]
+    pub fn new_meta_token(owner_id: AccountId, total_supply: U128) -> Self {
+        Self::new(
+            owner_id,
+            total_supply,
+            FungibleTokenMetadata {
+                spec: FT_METADATA_SPEC.to_string(),
+                name: "Token C".to_string(),
+                symbol: "C$".to_string(),
+                icon: Some(DATA_IMAGE_SVG_TOKEN_C.to_string()),
+                reference: None,
+                reference_hash: None,
+                decimals: 6,
+            },
+        )
+    }
+
+    #[init]
+    pub fn new(owner_id: AccountId, total_supply: U128, metadata: FungibleTokenMetadata) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        metadata.assert_valid();
+        let mut token = Self {
+            token: FungibleToken::new(b"a".to_vec()),
+            metadata: LazyOption::new(b"m".to_vec(), Some(&metadata)),
+        };
+
+        token.token.internal_register_account(&owner_id);
+        token.token.internal_deposit(&owner_id, total_supply.into());
+
+        FtMint {
+            owner_id: &owner_id,
+            amount: &total_supply,
+            memo: Some("Initial tokens supply is minted"),
+        }
+        .emit();
+
+        token
+    }
+
+    fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
+        log!("Closed @{} with {}", account_id, balance);
+    }
+
+    fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        log!("Account @{} burned {}", account_id, amount);
+    }
+}
+
+near_contract_standards::impl_fungible_token_core!(TokenC, token, on_tokens_burned);
+near_contract_standards::impl_fungible_token_storage!(TokenC, token, on_account_closed);
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for TokenC {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
+    }
+}